@@ -10,10 +10,21 @@ fn main() {
     )
     .expect("Unable to setup environment!");
 
-    let kmd = env
-        .compile_aasm(assembly)
-        .expect("Failed to compile assembly!");
+    let compile_result = env
+        .compile_aasm_source(
+            assembly,
+            "hello.s",
+            Some(concat!(env!("CARGO_MANIFEST_DIR"), "/examples").to_string()),
+        )
+        .expect("Failed to run aasm!");
 
-    println!("{}", kmd.aasm_terminal);
-    println!("{}", kmd.kmd);
+    if !compile_result.is_success() {
+        eprintln!("{}", compile_result.output().aasm_terminal);
+        panic!("Failed to compile assembly!");
+    }
+
+    let output = compile_result.into_output();
+
+    println!("{}", output.aasm_terminal);
+    println!("{}", output.kmd);
 }
@@ -615,7 +615,7 @@ void monitorOptionsMisc(uchar command) {
       ringBuffer* pBuff;
 
       getChar(&device);
-      pBuff = terminalTable[device][1];
+      pBuff = device < 16 ? terminalTable[device][1] : NULL;
       getChar(&length);
       temp = tempchar;
       while (length-- > 0) {
@@ -632,12 +632,12 @@ void monitorOptionsMisc(uchar command) {
       ringBuffer* pBuff;
 
       getChar(&device);
-      pBuff = terminalTable[device][0];
+      pBuff = device < 16 ? terminalTable[device][0] : NULL;
       getChar(&max_length);
-      available = countBuffer(&terminal0Tx); /* See how many chars we have */
       if (pBuff == NULL) {
         length = 0; /* Kill if no corresponding buffer */
       } else {
+        available = countBuffer(pBuff); /* See how many chars we have */
         length = available < max_length ? available : max_length;
       }
       sendChar(length);
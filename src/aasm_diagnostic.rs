@@ -0,0 +1,351 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum AasmDiagnosticSeverity {
+    Error,
+    Warning,
+}
+
+/// The kinds of error aasm can report, in the order they appear in aasm's `print_error`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum AasmErrorKind {
+    SyntaxError,
+    MnemonicNotFound,
+    LabelMissing,
+    BadRegister,
+    IllegalRegisterCombination,
+    RegisterListRequired,
+    MissingRightBrace,
+    ValueOutOfRange,
+    StringUnterminated,
+    LabelRedefined,
+    CommaExpected,
+    Garbage,
+    ExportedLabelNotDefined,
+    LabelRedefinedInconsistently,
+    FilenameMissing,
+    LeftBracketExpected,
+    RightBracketExpected,
+    AddressingModeError,
+    IllegalAddressingMode,
+    LeftBraceExpected,
+    OffsetOutOfRange,
+    CoprocessorExpected,
+    InstructionNotAvailable,
+    ConditionalExecutionForbidden,
+    BadCoprocessorOperation,
+    NoLabels,
+    EntryAlreadyDefined,
+    IncludeFileMissing,
+    ExclamationMarkExpected,
+    OffsetMisaligned,
+    BranchOutOfRange,
+    BranchToMisalignedTarget,
+    VariableRedefinedInconsistently,
+    IdentifierExpected,
+    TooManyNestedIfs,
+    EndifWithoutIf,
+    FloatingElse,
+    HashExpected,
+    ImportFileMissing,
+    OnlyAdrAllowedWithPc,
+    ShiftOperatorExpected,
+    HashOrRegisterExpected,
+    OperandExpected,
+    OperatorExpected,
+    MissingRightParenthesis,
+    ExtraRightParenthesis,
+    MathStackOverflow,
+    LabelNotFound,
+    LabelUndefined,
+    NumberOutOfRadix,
+    DivisionByZero,
+    OperandError,
+    BadLocalLabel,
+    LabelNotDefinedYet,
+
+    /// aasm's catch-all "Strange error", or a message this version of libiguana doesn't know
+    Unknown,
+}
+
+/// The messages aasm prints for each kind of error
+const ERROR_MESSAGES: [(&str, AasmErrorKind); 54] = [
+    ("Syntax error", AasmErrorKind::SyntaxError),
+    ("Mnemonic not found", AasmErrorKind::MnemonicNotFound),
+    ("Label missing", AasmErrorKind::LabelMissing),
+    ("Bad register", AasmErrorKind::BadRegister),
+    (
+        "Illegal register combination",
+        AasmErrorKind::IllegalRegisterCombination,
+    ),
+    (
+        "Register list required",
+        AasmErrorKind::RegisterListRequired,
+    ),
+    ("Missing '}'", AasmErrorKind::MissingRightBrace),
+    ("Value out of range", AasmErrorKind::ValueOutOfRange),
+    ("String unterminated", AasmErrorKind::StringUnterminated),
+    ("Label redefined", AasmErrorKind::LabelRedefined),
+    ("',' expected", AasmErrorKind::CommaExpected),
+    ("Garbage", AasmErrorKind::Garbage),
+    (
+        "Exported label not defined",
+        AasmErrorKind::ExportedLabelNotDefined,
+    ),
+    (
+        "Label redefined inconsistently",
+        AasmErrorKind::LabelRedefinedInconsistently,
+    ),
+    ("Filename missing", AasmErrorKind::FilenameMissing),
+    ("'[' expected", AasmErrorKind::LeftBracketExpected),
+    ("']' expected", AasmErrorKind::RightBracketExpected),
+    (
+        "Error in addressing mode",
+        AasmErrorKind::AddressingModeError,
+    ),
+    (
+        "Illegal addressing mode",
+        AasmErrorKind::IllegalAddressingMode,
+    ),
+    ("'{' expected", AasmErrorKind::LeftBraceExpected),
+    ("Offset out of range", AasmErrorKind::OffsetOutOfRange),
+    (
+        "Coprocessor specifier expected",
+        AasmErrorKind::CoprocessorExpected,
+    ),
+    (
+        "Instruction not available",
+        AasmErrorKind::InstructionNotAvailable,
+    ),
+    (
+        "Conditional execution forbidden",
+        AasmErrorKind::ConditionalExecutionForbidden,
+    ),
+    (
+        "Bad coprocessor operation",
+        AasmErrorKind::BadCoprocessorOperation,
+    ),
+    ("No labels! Position uncertain", AasmErrorKind::NoLabels),
+    ("Entry already defined", AasmErrorKind::EntryAlreadyDefined),
+    ("Include file missing", AasmErrorKind::IncludeFileMissing),
+    ("'!' expected", AasmErrorKind::ExclamationMarkExpected),
+    ("Offset misaligned", AasmErrorKind::OffsetMisaligned),
+    ("Branch out of range", AasmErrorKind::BranchOutOfRange),
+    (
+        "Branch to misaligned target",
+        AasmErrorKind::BranchToMisalignedTarget,
+    ),
+    (
+        "Variable redefined inconsistently",
+        AasmErrorKind::VariableRedefinedInconsistently,
+    ),
+    ("Identifier expected", AasmErrorKind::IdentifierExpected),
+    ("Too many nested IFs", AasmErrorKind::TooManyNestedIfs),
+    ("ENDIF without an IF", AasmErrorKind::EndifWithoutIf),
+    ("Floating ELSE", AasmErrorKind::FloatingElse),
+    ("'#' expected", AasmErrorKind::HashExpected),
+    ("Import file missing", AasmErrorKind::ImportFileMissing),
+    (
+        "Only ADR allowed with destination PC",
+        AasmErrorKind::OnlyAdrAllowedWithPc,
+    ),
+    (
+        "Shift operator expected",
+        AasmErrorKind::ShiftOperatorExpected,
+    ),
+    (
+        "'#' or register expected",
+        AasmErrorKind::HashOrRegisterExpected,
+    ),
+    ("Operand expected", AasmErrorKind::OperandExpected),
+    ("Operator expected", AasmErrorKind::OperatorExpected),
+    ("Missing ')'", AasmErrorKind::MissingRightParenthesis),
+    ("Extra ')'", AasmErrorKind::ExtraRightParenthesis),
+    ("Math stack overflow", AasmErrorKind::MathStackOverflow),
+    ("Label not found", AasmErrorKind::LabelNotFound),
+    ("Label undefined", AasmErrorKind::LabelUndefined),
+    ("Number out of radix", AasmErrorKind::NumberOutOfRadix),
+    ("Division by zero", AasmErrorKind::DivisionByZero),
+    ("Operand error", AasmErrorKind::OperandError),
+    ("Bad local label", AasmErrorKind::BadLocalLabel),
+    (
+        "Label not defined before this point",
+        AasmErrorKind::LabelNotDefinedYet,
+    ),
+];
+
+impl From<&str> for AasmErrorKind {
+    fn from(value: &str) -> Self {
+        ERROR_MESSAGES
+            .iter()
+            .find(|(message, _)| *message == value)
+            .map(|(_, kind)| *kind)
+            .unwrap_or(AasmErrorKind::Unknown)
+    }
+}
+
+/// An error or warning reported by aasm.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct AasmDiagnostic {
+    /// The file the error is in. This may be an included file rather than the one being assembled.
+    pub file: String,
+
+    /// The line number of the error (starting at 1)
+    pub line: u32,
+
+    /// The position on the line aasm points to (starting at 0), if it gave one
+    pub column: Option<u32>,
+
+    pub severity: AasmDiagnosticSeverity,
+
+    pub kind: AasmErrorKind,
+
+    /// The message as aasm printed it
+    pub message: String,
+
+    /// The source line that caused the error
+    pub source_line: String,
+}
+
+impl AasmDiagnostic {
+    /// Finds every diagnostic in aasm's terminal output. aasm prints each one as a header line
+    /// (`[Warning: ]<message> on line <line> of file: <file>`), then the source line, then a `^`
+    /// under the error position if it knows it.
+    pub fn parse_all(aasm_terminal: &str) -> Vec<Self> {
+        let lines = aasm_terminal.lines().collect::<Vec<_>>();
+
+        let mut diagnostics = Vec::new();
+
+        for (index, header) in lines.iter().enumerate() {
+            let Some((message, line, file)) = parse_header(header) else {
+                continue;
+            };
+
+            let (severity, message) = match message.strip_prefix("Warning: ") {
+                Some(message) => (AasmDiagnosticSeverity::Warning, message),
+                None => (AasmDiagnosticSeverity::Error, message),
+            };
+
+            let source_line = lines.get(index + 1).copied().unwrap_or_default();
+
+            let column = lines
+                .get(index + 2)
+                .filter(|caret_line| caret_line.trim_start() == "^")
+                .map(|caret_line| (caret_line.len() - 1) as u32);
+
+            diagnostics.push(Self {
+                file: file.to_string(),
+                line,
+                column,
+                severity,
+                kind: AasmErrorKind::from(message),
+                message: message.to_string(),
+                source_line: source_line.to_string(),
+            });
+        }
+
+        diagnostics
+    }
+}
+
+/// Splits a diagnostic header into its message, line number and file.
+fn parse_header(header: &str) -> Option<(&str, u32, &str)> {
+    let (rest, file) = header.split_once(" of file: ")?;
+    let (message, line) = rest.rsplit_once(" on line ")?;
+
+    Some((message, line.parse().ok()?, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What aasm prints for a file with two errors, one of which it can point to
+    const ERRORS: &str = "Input file: err.s
+Bad register on line 2 of file: err.s
+ MOV r1, r16
+         ^
+Error in addressing mode on line 3 of file: err.s
+ LDR r2, [r3
+Pass  0: terminating due to 2 errors
+No output generated.
+
+Complete.  0 passes performed.
+";
+
+    /// What aasm prints for a file that assembles with a warning
+    const WARNING: &str = "Input file: w1.s
+Warning: Exported label not defined on line 1 of file: w1.s
+ EXPORT nothere
+List file in: /dev/null
+
+1 pass performed.
+";
+
+    #[test]
+    fn parses_errors_with_and_without_a_column() {
+        assert_eq!(
+            AasmDiagnostic::parse_all(ERRORS),
+            vec![
+                AasmDiagnostic {
+                    file: String::from("err.s"),
+                    line: 2,
+                    column: Some(9),
+                    severity: AasmDiagnosticSeverity::Error,
+                    kind: AasmErrorKind::BadRegister,
+                    message: String::from("Bad register"),
+                    source_line: String::from(" MOV r1, r16"),
+                },
+                AasmDiagnostic {
+                    file: String::from("err.s"),
+                    line: 3,
+                    column: None,
+                    severity: AasmDiagnosticSeverity::Error,
+                    kind: AasmErrorKind::AddressingModeError,
+                    message: String::from("Error in addressing mode"),
+                    source_line: String::from(" LDR r2, [r3"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_warnings() {
+        assert_eq!(
+            AasmDiagnostic::parse_all(WARNING),
+            vec![AasmDiagnostic {
+                file: String::from("w1.s"),
+                line: 1,
+                column: None,
+                severity: AasmDiagnosticSeverity::Warning,
+                kind: AasmErrorKind::ExportedLabelNotDefined,
+                message: String::from("Exported label not defined"),
+                source_line: String::from(" EXPORT nothere"),
+            }]
+        );
+    }
+
+    #[test]
+    fn ignores_lines_that_are_not_diagnostics() {
+        let terminal =
+            "Input file: ok.s\nList file in: /dev/null\n\nComplete.  2 passes performed.\n";
+
+        assert!(AasmDiagnostic::parse_all(terminal).is_empty());
+    }
+
+    #[test]
+    fn unknown_messages_are_unknown() {
+        let diagnostics = AasmDiagnostic::parse_all("Strange error on line 7 of file: a.s\n X\n");
+
+        assert_eq!(diagnostics[0].kind, AasmErrorKind::Unknown);
+        assert_eq!(diagnostics[0].line, 7);
+    }
+
+    #[test]
+    fn parse_header_splits_on_the_last_line_number() {
+        assert_eq!(
+            parse_header("Label undefined on line 12 of file: dir/on line 3.s"),
+            Some(("Label undefined", 12, "dir/on line 3.s"))
+        );
+        assert_eq!(parse_header("Bad register on line x of file: a.s"), None);
+        assert_eq!(parse_header("Input file: a.s"), None);
+    }
+}
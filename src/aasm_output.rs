@@ -1,5 +1,155 @@
+use crate::{
+    aasm_diagnostic::{AasmDiagnostic, AasmDiagnosticSeverity},
+    aasm_symbol::AasmSymbolTable,
+};
+
 #[derive(uniffi::Record)]
 pub struct AasmOutput {
     pub kmd: String,
     pub aasm_terminal: String,
+
+    /// The errors and warnings aasm reported
+    pub diagnostics: Vec<AasmDiagnostic>,
+
+    /// The number of passes aasm performed, if it got as far as saying
+    pub pass_count: Option<u32>,
+
+    /// Whether aasm threw away its output because of errors ("No output generated.")
+    pub output_suppressed: bool,
+
+    /// aasm's exit code, or `None` if it was killed by a signal
+    pub exit_code: Option<i32>,
+
+    /// The ELF file, if it was asked for in the `CompileOptions`
+    pub elf: Option<Vec<u8>>,
+
+    /// The hex dump, if it was asked for in the `CompileOptions`
+    pub hex: Option<String>,
+
+    /// The symbol table, if it was asked for in the `CompileOptions`
+    pub symbol_table: Option<AasmSymbolTable>,
+
+    /// The Verilog readmemh file, if it was asked for in the `CompileOptions`
+    pub verilog: Option<String>,
+}
+
+impl AasmOutput {
+    /// Creates an `AasmOutput`, parsing the diagnostics and pass count out of `aasm_terminal`. The
+    /// optional outputs are left empty.
+    pub fn new(kmd: String, aasm_terminal: String, exit_code: Option<i32>) -> Self {
+        let diagnostics = AasmDiagnostic::parse_all(&aasm_terminal);
+
+        let pass_count = aasm_terminal.lines().find_map(parse_pass_count);
+
+        let output_suppressed = aasm_terminal
+            .lines()
+            .any(|line| line == "No output generated.");
+
+        Self {
+            kmd,
+            aasm_terminal,
+            diagnostics,
+            pass_count,
+            output_suppressed,
+            exit_code,
+            elf: None,
+            hex: None,
+            symbol_table: None,
+            verilog: None,
+        }
+    }
+
+    /// Whether aasm assembled the file without errors, meaning `kmd` is complete. aasm exits with 0
+    /// even if it can't open the input file, so it also has to have reported a pass count.
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+            && self.pass_count.is_some()
+            && !self.output_suppressed
+            && !self
+                .diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.severity == AasmDiagnosticSeverity::Error)
+    }
+}
+
+/// aasm finishes with either "1 pass performed.", "Complete.  <n> passes performed." or, if it
+/// gave up, "Pass <n>: terminating due to <errors> error(s)".
+fn parse_pass_count(line: &str) -> Option<u32> {
+    if line == "1 pass performed." {
+        return Some(1);
+    }
+
+    if let Some(passes) = line
+        .strip_prefix("Complete.")
+        .and_then(|rest| rest.strip_suffix(" passes performed."))
+    {
+        return passes.trim().parse().ok();
+    }
+
+    line.strip_prefix("Pass ")?
+        .split_once(": terminating")?
+        .0
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_pass_count_format() {
+        assert_eq!(parse_pass_count("1 pass performed."), Some(1));
+        assert_eq!(parse_pass_count("Complete.  2 passes performed."), Some(2));
+        assert_eq!(
+            parse_pass_count("Pass  0: terminating due to 2 errors"),
+            Some(0)
+        );
+        assert_eq!(parse_pass_count("Input file: a.s"), None);
+    }
+
+    #[test]
+    fn failed_assembly_is_not_a_success() {
+        let terminal = "Input file: err.s
+Bad register on line 2 of file: err.s
+ MOV r1, r16
+         ^
+Pass  0: terminating due to 1 errors
+No output generated.
+
+Complete.  0 passes performed.
+";
+
+        let output = AasmOutput::new(String::new(), terminal.to_string(), Some(0));
+
+        assert_eq!(output.pass_count, Some(0));
+        assert!(output.output_suppressed);
+        assert!(!output.succeeded());
+    }
+
+    #[test]
+    fn warnings_do_not_stop_a_success() {
+        let terminal = "Input file: w1.s
+Warning: Exported label not defined on line 1 of file: w1.s
+ EXPORT nothere
+List file in: /dev/null
+
+1 pass performed.
+";
+
+        let output = AasmOutput::new(String::new(), terminal.to_string(), Some(0));
+
+        assert_eq!(output.pass_count, Some(1));
+        assert!(!output.output_suppressed);
+        assert!(output.succeeded());
+    }
+
+    #[test]
+    fn missing_input_file_is_not_a_success() {
+        // aasm exits with 0 without saying anything about passes when it can't read the input
+        let output = AasmOutput::new(String::new(), String::from("Input file: a.s\n"), Some(0));
+
+        assert!(!output.succeeded());
+    }
 }
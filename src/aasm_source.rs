@@ -0,0 +1,118 @@
+use std::path::Path;
+
+/// The directives that take a filename, which aasm resolves relative to the directory of the file
+/// being assembled.
+const FILE_DIRECTIVES: [&str; 3] = ["include", "get", "import"];
+
+/// Rewrites every `INCLUDE`/`GET`/`IMPORT` with a relative filename to use an absolute path from
+/// `base_directory`. This is needed when assembling source from a temp file, since aasm would
+/// otherwise look for included files next to the temp file. Line numbers are left unchanged.
+pub fn resolve_includes(source: &str, base_directory: &Path) -> String {
+    source
+        .split('\n')
+        .map(|line| resolve_include(line, base_directory).unwrap_or_else(|| line.to_string()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns the rewritten line if `line` includes a relative filename, or `None` if it should be
+/// left alone.
+fn resolve_include(line: &str, base_directory: &Path) -> Option<String> {
+    let is_space = |c: char| c == ' ' || c == '\t';
+    let is_eol = |c: char| c == ';' || c == '\r';
+
+    // Anything at the start of a line is a label, unless the whole line is a comment
+    if line.starts_with(is_eol) {
+        return None;
+    }
+
+    let label_end = line.find(is_space).unwrap_or(line.len());
+    let directive_start = label_end + line[label_end..].find(|c: char| !is_space(c))?;
+    let directive_end = directive_start
+        + line[directive_start..]
+            .find(is_space)
+            .unwrap_or(line.len() - directive_start);
+
+    let directive = &line[directive_start..directive_end];
+
+    if !FILE_DIRECTIVES
+        .iter()
+        .any(|file_directive| file_directive.eq_ignore_ascii_case(directive))
+    {
+        return None;
+    }
+
+    let filename_start = directive_end + line[directive_end..].find(|c: char| !is_space(c))?;
+    let filename_end = filename_start
+        + line[filename_start..]
+            .find(|c: char| is_space(c) || is_eol(c))
+            .unwrap_or(line.len() - filename_start);
+
+    let filename = &line[filename_start..filename_end];
+
+    if filename.is_empty() || filename.starts_with('/') {
+        return None;
+    }
+
+    let resolved = base_directory.join(filename);
+
+    Some(format!(
+        "{}{}{}",
+        &line[..filename_start],
+        resolved.to_string_lossy(),
+        &line[filename_end..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(source: &str) -> String {
+        resolve_includes(source, Path::new("/home/user/project"))
+    }
+
+    #[test]
+    fn resolves_relative_filenames_for_every_file_directive() {
+        assert_eq!(
+            resolve("\tINCLUDE defs.s\n\tget lib/io.s\n\tImport ../shared/maths.s\n"),
+            "\tINCLUDE /home/user/project/defs.s\n\tget /home/user/project/lib/io.s\n\tImport \
+             /home/user/project/../shared/maths.s\n"
+        );
+    }
+
+    #[test]
+    fn leaves_absolute_filenames_alone() {
+        let source = "\tINCLUDE /usr/share/komodo/defs.s\n\tGET /tmp/io.s\n";
+
+        assert_eq!(resolve(source), source);
+    }
+
+    #[test]
+    fn keeps_labels_comments_and_line_endings() {
+        assert_eq!(
+            resolve("start INCLUDE defs.s ; constants\r\n\tGET io.s\r\n"),
+            "start INCLUDE /home/user/project/defs.s ; constants\r\n\tGET \
+             /home/user/project/io.s\r\n"
+        );
+
+        assert_eq!(
+            resolve("\tINCLUDE defs.s;constants"),
+            "\tINCLUDE /home/user/project/defs.s;constants"
+        );
+    }
+
+    #[test]
+    fn ignores_other_lines() {
+        let source = "include MOV r0, #1\n\tMOV r0, #1 ; INCLUDE defs.s\n; GET io.s\n\tINCLUDE\n\n";
+
+        assert_eq!(resolve(source), source);
+    }
+
+    #[test]
+    fn keeps_the_line_count() {
+        let source = "\tINCLUDE a.s\n\n\tGET b.s\n";
+
+        assert_eq!(resolve(source).lines().count(), source.lines().count());
+    }
+}
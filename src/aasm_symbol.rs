@@ -0,0 +1,260 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum AasmSymbolKind {
+    /// Defined with `DEF`
+    Constant,
+
+    /// Defined with `EQU`
+    Value,
+
+    /// An offset into a `RECORD`/`STRUCT`
+    Offset,
+
+    ArmLabel,
+    ThumbLabel,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct AasmSymbol {
+    pub name: String,
+
+    /// aasm's internal identifier for the symbol
+    pub identifier: u32,
+
+    /// The value of the symbol, or `None` if it was never defined
+    pub value: Option<u32>,
+
+    pub kind: AasmSymbolKind,
+
+    pub is_exported: bool,
+}
+
+/// A numbered local label.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct AasmLocalLabel {
+    pub label: u32,
+    pub value: u32,
+}
+
+/// A literal aasm placed in a literal pool (e.g. for `LDR R0, =0x12345678`).
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct AasmLiteral {
+    pub memory_address: u32,
+    pub value: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, uniffi::Record)]
+pub struct AasmSymbolTable {
+    pub symbols: Vec<AasmSymbol>,
+
+    /// Only filled if `include_local_labels` was set
+    pub local_labels: Vec<AasmLocalLabel>,
+
+    /// Only filled if `include_literals` was set
+    pub literals: Vec<AasmLiteral>,
+}
+
+/// The sections of aasm's symbol table file
+enum Section {
+    Symbols,
+    LocalLabels,
+    Literals,
+}
+
+impl AasmSymbolTable {
+    /// Parses a symbol table file written by aasm's `-s` option. Lines that aren't understood are
+    /// skipped.
+    pub fn parse(symbol_file: &str) -> Self {
+        let mut symbol_table = Self::default();
+        let mut section = Section::Symbols;
+
+        for line in symbol_file.lines() {
+            if line.starts_with("Symbol table:") {
+                section = Section::Symbols;
+                continue;
+            }
+
+            if line.starts_with("Local (labels") {
+                section = Section::LocalLabels;
+                continue;
+            }
+
+            // The literal pool header is on the same line as the column headings
+            if line.starts_with("Literal pool:") {
+                section = Section::Literals;
+                continue;
+            }
+
+            match section {
+                Section::Symbols => symbol_table.symbols.extend(parse_symbol(line)),
+                Section::LocalLabels => symbol_table.local_labels.extend(parse_local_label(line)),
+                Section::Literals => symbol_table.literals.extend(parse_literal(line)),
+            }
+        }
+
+        symbol_table
+    }
+}
+
+/// Parses a line like `main  ........  0000000C  00000024  ARM label   (exported)`. Names are
+/// padded with dots, which are skipped.
+fn parse_symbol(line: &str) -> Option<AasmSymbol> {
+    let mut fields = line
+        .split_whitespace()
+        .filter(|field| !field.chars().all(|c| c == '.'));
+
+    let name = fields.next()?;
+    let identifier = u32::from_str_radix(fields.next()?, 16).ok()?;
+
+    let value = match fields.next()? {
+        "Undefined" => None,
+        value => Some(u32::from_str_radix(value, 16).ok()?),
+    };
+
+    let kind = match fields.next()? {
+        "Constant" => AasmSymbolKind::Constant,
+        "Value" => AasmSymbolKind::Value,
+        "Offset" => AasmSymbolKind::Offset,
+        "ARM" => AasmSymbolKind::ArmLabel,
+        "Thumb" => AasmSymbolKind::ThumbLabel,
+        _ => return None,
+    };
+
+    let is_exported = fields.any(|field| field == "(exported)");
+
+    Some(AasmSymbol {
+        name: name.to_string(),
+        identifier,
+        value,
+        kind,
+        is_exported,
+    })
+}
+
+/// Parses a line like `                     3:  00000010`
+fn parse_local_label(line: &str) -> Option<AasmLocalLabel> {
+    let (label, value) = line.split_once(':')?;
+
+    Some(AasmLocalLabel {
+        label: label.trim().parse().ok()?,
+        value: u32::from_str_radix(value.trim(), 16).ok()?,
+    })
+}
+
+/// Parses a line like `              00000040:  12345678`
+fn parse_literal(line: &str) -> Option<AasmLiteral> {
+    let (memory_address, value) = line.split_once(':')?;
+
+    Some(AasmLiteral {
+        memory_address: u32::from_str_radix(memory_address.trim(), 16).ok()?,
+        value: u32::from_str_radix(value.trim(), 16).ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What aasm's `-sdlp` writes for a file with an `EQU`, a `DEF`, an exported ARM label, a
+    /// Thumb label, two local labels and two literals
+    const ALL_SECTIONS: &str = "
+Symbol table: Labels
+Label                                ID      Value    Type
+size  ..........................  00000000  00000004  Value      
+k  .............................  00000001  00000007  Constant   
+main  ..........................  00000002  00000000  ARM label   (exported)
+th  ............................  00000003  0000000C  Thumb label
+
+Local (labels in order of definition):
+           Local Label     Value
+                     1:  00000004
+                     2:  00000008
+
+Literal pool:  Address     Value
+              00000010:  12345678
+              00000014:  CAFEF00D
+";
+
+    /// The same file with `-s`, which sorts the symbols by name and leaves out the other sections
+    const SYMBOLS_ONLY: &str = "
+Symbol table: Labels
+Label                                ID      Value    Type
+k  .............................  00000001  00000007  Constant   
+main  ..........................  00000002  00000000  ARM label   (exported)
+size  ..........................  00000000  00000004  Value      
+th  ............................  00000003  0000000C  Thumb label
+";
+
+    fn symbol(
+        name: &str,
+        identifier: u32,
+        value: u32,
+        kind: AasmSymbolKind,
+        is_exported: bool,
+    ) -> AasmSymbol {
+        AasmSymbol {
+            name: name.to_string(),
+            identifier,
+            value: Some(value),
+            kind,
+            is_exported,
+        }
+    }
+
+    #[test]
+    fn parses_every_section() {
+        assert_eq!(
+            AasmSymbolTable::parse(ALL_SECTIONS),
+            AasmSymbolTable {
+                symbols: vec![
+                    symbol("size", 0, 4, AasmSymbolKind::Value, false),
+                    symbol("k", 1, 7, AasmSymbolKind::Constant, false),
+                    symbol("main", 2, 0, AasmSymbolKind::ArmLabel, true),
+                    symbol("th", 3, 0xC, AasmSymbolKind::ThumbLabel, false),
+                ],
+                local_labels: vec![
+                    AasmLocalLabel { label: 1, value: 4 },
+                    AasmLocalLabel { label: 2, value: 8 },
+                ],
+                literals: vec![
+                    AasmLiteral {
+                        memory_address: 0x10,
+                        value: 0x1234_5678,
+                    },
+                    AasmLiteral {
+                        memory_address: 0x14,
+                        value: 0xCAFE_F00D,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_symbols_on_their_own() {
+        let symbol_table = AasmSymbolTable::parse(SYMBOLS_ONLY);
+
+        let names = symbol_table
+            .symbols
+            .iter()
+            .map(|symbol| symbol.name.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["k", "main", "size", "th"]);
+        assert!(symbol_table.local_labels.is_empty());
+        assert!(symbol_table.literals.is_empty());
+    }
+
+    #[test]
+    fn parses_undefined_symbols() {
+        assert_eq!(
+            parse_symbol("ext  ...........................  00000004  Undefined  ARM label  "),
+            Some(AasmSymbol {
+                name: String::from("ext"),
+                identifier: 4,
+                value: None,
+                kind: AasmSymbolKind::ArmLabel,
+                is_exported: false,
+            })
+        );
+    }
+}
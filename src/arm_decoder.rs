@@ -1,6 +1,10 @@
 use thiserror::Error;
 use yaxpeax_arch::{Decoder, ReaderBuilder};
-use yaxpeax_arm::armv7::{ARMv7, DecodeError};
+use yaxpeax_arm::armv7::{
+    ARMv7, ConditionCode, DecodeError, InstDecoder, Instruction, Opcode, Operand, Reg, RegShift,
+};
+
+use crate::decoded_instruction::DecodedInstruction;
 
 /// A wrapper around yaxpeax's `DecoderError` so that it can be passed through uniffi
 #[derive(Debug, Error, uniffi::Error)]
@@ -14,17 +18,367 @@ pub enum DecoderError {
 /// uniffi doesn't like associated functions.
 #[uniffi::export]
 pub fn decode_instruction(word: u32) -> Result<String, DecoderError> {
+    Ok(decode_arm(word)?.to_string().to_uppercase())
+}
+
+/// Decodes the given word into a Thumb instruction. The first halfword is the bottom 16 bits of
+/// `word`, which is how it would be read from memory. If the first halfword is a complete (16-bit)
+/// instruction, the top 16 bits are ignored.
+#[uniffi::export]
+pub fn decode_instruction_thumb(word: u32) -> Result<String, DecoderError> {
+    let decoder = InstDecoder::default_thumb();
+
+    Ok(decode_with(&decoder, word)?.to_string().to_uppercase())
+}
+
+/// Like [`decode_instruction`], but breaks the instruction down into its parts. `address` is where
+/// the instruction is in memory, which is needed to work out branch targets.
+#[uniffi::export]
+pub fn decode_instruction_details(
+    word: u32,
+    address: u32,
+) -> Result<DecodedInstruction, DecoderError> {
+    Ok(DecodedInstruction::new(&decode_arm(word)?, address))
+}
+
+/// Like [`decode_instruction_thumb`], but breaks the instruction down into its parts. `address` is
+/// where the instruction is in memory, which is needed to work out branch targets.
+#[uniffi::export]
+pub fn decode_instruction_details_thumb(
+    word: u32,
+    address: u32,
+) -> Result<DecodedInstruction, DecoderError> {
+    let decoder = InstDecoder::default_thumb();
+
+    Ok(DecodedInstruction::new(
+        &decode_with(&decoder, word)?,
+        address,
+    ))
+}
+
+/// The condition codes in the order of their encodings
+const CONDITION_CODES: [ConditionCode; 15] = [
+    ConditionCode::EQ,
+    ConditionCode::NE,
+    ConditionCode::HS,
+    ConditionCode::LO,
+    ConditionCode::MI,
+    ConditionCode::PL,
+    ConditionCode::VS,
+    ConditionCode::VC,
+    ConditionCode::HI,
+    ConditionCode::LS,
+    ConditionCode::GE,
+    ConditionCode::LT,
+    ConditionCode::GT,
+    ConditionCode::LE,
+    ConditionCode::AL,
+];
+
+/// Decodes an ARM instruction, working around the parts of yaxpeax that don't suit KoMo programs.
+fn decode_arm(word: u32) -> Result<Instruction, DecoderError> {
+    let decoder = <ARMv7 as yaxpeax_arch::Arch>::Decoder::default(); // what a line
+
+    let condition = (word >> 28) as usize;
+    let rd = Operand::Reg(Reg::from_u8((word >> 12) as u8 & 0b1111));
+    let rn = Reg::from_u8((word >> 16) as u8 & 0b1111);
+
+    match decode_with(&decoder, word) {
+        // yaxpeax leaves the hints (which are MSRs that don't write anything) undecoded
+        Ok(_) if word & 0x0FFF_FF00 == 0x0320_F000 && word & 0xFF <= 4 => {
+            let opcode = match word & 0xFF {
+                0 => Opcode::NOP,
+                1 => Opcode::YIELD,
+                2 => Opcode::WFE,
+                3 => Opcode::WFI,
+                _ => Opcode::SEV,
+            };
+
+            Ok(arm_instruction(
+                condition,
+                opcode,
+                [Operand::Nothing, Operand::Nothing],
+                false,
+            ))
+        }
+
+        Ok(mut instruction) => {
+            let opcode = instruction.opcode;
+            let operands = instruction.operands;
+            let has_three_operands = operands[2] != Operand::Nothing;
+
+            match opcode {
+                // yaxpeax calls both `ADD Rd, PC, #imm` and `SUB Rd, PC, #imm` ADR, which loses
+                // the sign
+                Opcode::ADR if (word >> 21) & 0b1111 == 0b0010 => instruction.opcode = Opcode::SUB,
+
+                // yaxpeax includes the unused Rd of comparisons (and says they have an S suffix)...
+                Opcode::TST | Opcode::TEQ | Opcode::CMP | Opcode::CMN if has_three_operands => {
+                    instruction.operands =
+                        [operands[1], operands[2], Operand::Nothing, Operand::Nothing];
+                    instruction.s = false;
+                }
+
+                // ...and the unused Rn of moves
+                Opcode::MOV | Opcode::MVN if has_three_operands => {
+                    instruction.operands =
+                        [operands[0], operands[2], Operand::Nothing, Operand::Nothing];
+                }
+
+                _ => {}
+            }
+
+            Ok(instruction)
+        }
+
+        // yaxpeax doesn't decode SWIs (or any coprocessor instructions)
+        Err(DecoderError::DecodeError(DecodeError::Incomplete))
+            if word & 0x0F00_0000 == 0x0F00_0000 && condition != 0b1111 =>
+        {
+            Ok(arm_instruction(
+                condition,
+                Opcode::SVC,
+                [Operand::Imm32(word & 0x00FF_FFFF), Operand::Nothing],
+                false,
+            ))
+        }
+
+        // yaxpeax rejects MOV/MVN with a register-specified shift (`MOV R0, R1, LSL R2`)
+        Err(_) if word & 0x0DE0_0090 == 0x01A0_0010 && condition != 0b1111 => {
+            let opcode = if word & 0x0040_0000 == 0 {
+                Opcode::MOV
+            } else {
+                Opcode::MVN
+            };
+
+            // yaxpeax's `RegShift` uses the same layout as bits 0-11 of the instruction
+            Ok(arm_instruction(
+                condition,
+                opcode,
+                [
+                    rd,
+                    Operand::RegShift(RegShift::from_raw((word & 0xFFF) as u16)),
+                ],
+                word & 0x0010_0000 != 0,
+            ))
+        }
+
+        // yaxpeax also rejects LDRSB/LDRSH with an immediate offset
+        Err(_) if word & 0x0E50_00D0 == 0x0050_00D0 && condition != 0b1111 => {
+            let opcode = if word & 0x20 == 0 {
+                Opcode::LDRSB
+            } else {
+                Opcode::LDRSH
+            };
+
+            let offset = (((word >> 4) & 0xF0) | (word & 0xF)) as u16;
+            let add = word & 0x0080_0000 != 0;
+
+            let address = if word & 0x0100_0000 != 0 {
+                Operand::RegDerefPreindexOffset(rn, offset, add, word & 0x0020_0000 != 0)
+            } else {
+                Operand::RegDerefPostindexOffset(rn, offset, add, false)
+            };
+
+            Ok(arm_instruction(condition, opcode, [rd, address], false))
+        }
+
+        Err(error) => Err(error),
+    }
+}
+
+/// Builds an ARM instruction with up to two operands, for the instructions yaxpeax can't decode itself.
+fn arm_instruction(
+    condition: usize,
+    opcode: Opcode,
+    operands: [Operand; 2],
+    s: bool,
+) -> Instruction {
+    Instruction {
+        condition: CONDITION_CODES[condition],
+        opcode,
+        operands: [operands[0], operands[1], Operand::Nothing, Operand::Nothing],
+        s,
+        wide: false,
+        thumb_w: false,
+        thumb: false,
+    }
+}
+
+fn decode_with(decoder: &InstDecoder, word: u32) -> Result<Instruction, DecoderError> {
     let word_bytes: &[u8] = &word.to_le_bytes();
 
     let mut reader = ReaderBuilder::<u32, u8>::read_from(word_bytes);
 
-    let decoder = <ARMv7 as yaxpeax_arch::Arch>::Decoder::default(); // what a line
+    Ok(decoder.decode(&mut reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoded_instruction::{DecodedOperand, MemoryOffset, Shift, ShiftAmount, ShiftKind};
+
+    fn register(register: u8) -> DecodedOperand {
+        DecodedOperand::Register { register }
+    }
+
+    #[test]
+    fn hints() {
+        assert_eq!(decode_instruction(0xE320_F000).unwrap(), "NOP");
+        assert_eq!(decode_instruction(0xE320_F001).unwrap(), "YIELD");
+
+        let instruction = decode_instruction_details(0xE320_F000, 0x100).unwrap();
+
+        assert_eq!(instruction.mnemonic, "NOP");
+        assert!(instruction.operands.is_empty());
+    }
+
+    #[test]
+    fn pc_relative_sub_keeps_its_sign() {
+        // SUB r0, pc, #8
+        let instruction = decode_instruction_details(0xE24F_0008, 0x104).unwrap();
+
+        assert_eq!(instruction.text, "SUB R0, PC, 0X8");
+        assert_eq!(instruction.adr_target, Some(0x104));
+
+        // ADD r0, pc, #8
+        let instruction = decode_instruction_details(0xE28F_0008, 0x124).unwrap();
+
+        assert_eq!(instruction.mnemonic, "ADR");
+        assert_eq!(instruction.adr_target, Some(0x134));
+    }
+
+    #[test]
+    fn comparisons_drop_rd_and_the_s_suffix() {
+        // CMP r1, r2
+        let instruction = decode_instruction_details(0xE151_0002, 0x108).unwrap();
+
+        assert_eq!(instruction.text, "CMP R1, R2");
+        assert_eq!(instruction.operands, vec![register(1), register(2)]);
+        assert!(instruction.sets_flags);
+    }
+
+    #[test]
+    fn moves_drop_rn() {
+        // MOV r0, r1
+        let instruction = decode_instruction_details(0xE1A0_0001, 0x10C).unwrap();
+
+        assert_eq!(instruction.text, "MOV R0, R1");
+        assert_eq!(instruction.operands, vec![register(0), register(1)]);
+    }
+
+    #[test]
+    fn swis() {
+        // SWI 2
+        let instruction = decode_instruction_details(0xEF00_0002, 0x110).unwrap();
+
+        assert_eq!(instruction.text, "SVC 0X2");
+        assert_eq!(
+            instruction.operands,
+            vec![DecodedOperand::Immediate { value: 2 }]
+        );
+
+        // SWINE 0x123456
+        assert_eq!(decode_instruction(0x1F12_3456).unwrap(), "SVCNE 0X123456");
+    }
+
+    #[test]
+    fn moves_with_a_register_shift() {
+        // MOVS r3, r4, ROR r5
+        let instruction = decode_instruction_details(0xE1B0_3574, 0x128).unwrap();
+
+        assert_eq!(instruction.text, "MOVS R3, R4, ROR R5");
+        assert!(instruction.sets_flags);
+        assert_eq!(
+            instruction.operands,
+            vec![
+                register(3),
+                DecodedOperand::ShiftedRegister {
+                    register: 4,
+                    shift: Shift {
+                        kind: ShiftKind::Ror,
+                        amount: ShiftAmount::Register { register: 5 },
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn signed_loads_with_an_immediate_offset() {
+        // LDRSB r0, [r1, #4]
+        let instruction = decode_instruction_details(0xE1D1_00D4, 0x118).unwrap();
+
+        assert_eq!(instruction.text, "LDRSB R0, [R1, 0X4]");
+        assert_eq!(
+            instruction.operands[1],
+            DecodedOperand::Memory {
+                base: 1,
+                offset: MemoryOffset::Immediate { value: 4 },
+                subtract: false,
+                is_preindexed: true,
+                writeback: false,
+            }
+        );
+
+        // LDRSH r0, [r1, #-6]!
+        let instruction = decode_instruction_details(0xE171_00F6, 0x11C).unwrap();
+
+        assert_eq!(instruction.text, "LDRSH R0, [R1, -0X6]!");
+        assert_eq!(
+            instruction.operands[1],
+            DecodedOperand::Memory {
+                base: 1,
+                offset: MemoryOffset::Immediate { value: 6 },
+                subtract: true,
+                is_preindexed: true,
+                writeback: true,
+            }
+        );
+
+        // LDRSH r2, [r3], #2
+        let instruction = decode_instruction_details(0xE0D3_20F2, 0x12C).unwrap();
+
+        assert_eq!(instruction.text, "LDRSH R2, [R3], 0X2");
+        assert_eq!(
+            instruction.operands[1],
+            DecodedOperand::Memory {
+                base: 3,
+                offset: MemoryOffset::Immediate { value: 2 },
+                subtract: false,
+                is_preindexed: false,
+                writeback: true,
+            }
+        );
+    }
+
+    #[test]
+    fn arm_branch_targets() {
+        // B 0x200
+        let instruction = decode_instruction_details(0xEA00_0036, 0x120).unwrap();
+
+        assert_eq!(instruction.branch_target, Some(0x200));
+    }
+
+    #[test]
+    fn thumb_branch_targets() {
+        // BEQ 0x110, which yaxpeax biases by a halfword
+        let instruction = decode_instruction_details_thumb(0xD006, 0x100).unwrap();
+
+        assert_eq!(instruction.text, "BEQ $+0XE");
+        assert_eq!(instruction.branch_target, Some(0x110));
+        assert_eq!(instruction.length, 2);
+
+        // B 0x120, which it doesn't
+        let instruction = decode_instruction_details_thumb(0xE00D, 0x102).unwrap();
+
+        assert_eq!(instruction.branch_target, Some(0x120));
 
-    // It'd make way more sense to return the entire instruction here but uniffi would make that a
-    // massive pain
-    let instruction_string = decoder
-        .decode(&mut reader)
-        .map(|instruction| instruction.to_string().to_uppercase())?;
+        // BL 0x400
+        let instruction = decode_instruction_details_thumb(0xF97C_F000, 0x104).unwrap();
 
-    Ok(instruction_string)
+        assert_eq!(instruction.mnemonic, "BL");
+        assert_eq!(instruction.branch_target, Some(0x400));
+        assert_eq!(instruction.length, 4);
+    }
 }
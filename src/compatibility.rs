@@ -0,0 +1,367 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    decoded_instruction::DecodedInstruction,
+    disassembly,
+    kmd_extensions::KmdExtensions,
+    kmdparse_types::{token::KmdparseToken, word::KmdparseWord},
+};
+
+/// The SWIs jimulator's `mySystem` handles itself
+const HANDLED_SWIS: [u32; 5] = [0, 1, 2, 3, 4];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum CompatibilityIssueKind {
+    /// jimulator takes the undefined instruction exception, which jumps to 0x04
+    Undefined,
+
+    /// jimulator doesn't trap, but runs the encoding as a different instruction
+    Misexecuted,
+
+    /// The instruction has the NV condition, which jimulator treats as never
+    NeverExecuted,
+
+    /// A SWI jimulator doesn't handle, which jumps to the SWI vector at 0x08
+    UnhandledSwi,
+}
+
+/// An instruction in the loaded program that won't do what it says when jimulator runs it.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct CompatibilityIssue {
+    pub memory_address: u32,
+
+    /// The disassembled instruction
+    pub instruction: String,
+
+    /// The line of source the instruction came from, as it appears in the KMD
+    pub source_line: String,
+
+    pub kind: CompatibilityIssueKind,
+
+    /// Why jimulator can't run the instruction, for showing to students
+    pub explanation: String,
+}
+
+/// The routines jimulator's `execute` hands an instruction to
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Handler {
+    DataProcessing,
+    Multiply,
+    HalfwordTransfer,
+    Swap,
+    StatusTransfer,
+    BranchExchange,
+    Breakpoint,
+    CountLeadingZeros,
+    SingleTransfer,
+    BlockTransfer,
+    Branch,
+    Swi(u32),
+
+    /// Thumb instructions, which jimulator decodes by their format rather than one by one
+    Thumb,
+
+    /// A Thumb encoding jimulator mistakes for another instruction, which it describes
+    ThumbMistaken(&'static str),
+
+    Coprocessor,
+    Undefined,
+    Never,
+}
+
+impl Handler {
+    /// What jimulator thinks the instruction is, for explanations
+    fn description(self) -> &'static str {
+        match self {
+            Self::DataProcessing => "a data processing instruction",
+            Self::Multiply => "a multiply",
+            Self::HalfwordTransfer => "a halfword or signed byte transfer",
+            Self::Swap => "a swap",
+            Self::StatusTransfer => "a status register transfer",
+            Self::BranchExchange => "a branch and exchange",
+            Self::Breakpoint => "a breakpoint",
+            Self::CountLeadingZeros => "CLZ",
+            Self::SingleTransfer => "a load or store",
+            Self::BlockTransfer => "a load or store multiple",
+            Self::Branch => "a branch",
+            Self::Swi(_) => "a SWI",
+            Self::Thumb => "a Thumb instruction",
+            Self::ThumbMistaken(description) => description,
+            Self::Coprocessor | Self::Undefined | Self::Never => "nothing",
+        }
+    }
+
+    /// Whether jimulator's handler actually implements `mnemonic`
+    fn implements(self, mnemonic: &str) -> bool {
+        let mnemonics: &[&str] = match self {
+            Self::DataProcessing => &[
+                "AND", "EOR", "SUB", "RSB", "ADD", "ADC", "SBC", "RSC", "TST", "TEQ", "CMP", "CMN",
+                "ORR", "MOV", "BIC", "MVN", "ADR", "LSL", "LSR", "ASR", "ROR", "RRX",
+            ],
+            Self::Multiply => &["MUL", "MLA", "UMULL", "UMLAL", "SMULL", "SMLAL"],
+            Self::HalfwordTransfer => &["LDRH", "STRH", "LDRSB", "LDRSH"],
+            Self::Swap => &["SWP", "SWPB"],
+
+            // The hints are MSRs that don't write anything
+            Self::StatusTransfer => &["MRS", "MSR", "NOP", "YIELD", "WFE", "WFI", "SEV"],
+
+            Self::BranchExchange => &["BX", "BLX"],
+            Self::Breakpoint => &["BKPT"],
+            Self::CountLeadingZeros => &["CLZ"],
+            Self::SingleTransfer => &[
+                "LDR", "STR", "LDRB", "STRB", "LDRT", "STRT", "LDRBT", "STRBT",
+            ],
+            Self::BlockTransfer => {
+                return mnemonic.starts_with("LDM")
+                    || mnemonic.starts_with("STM")
+                    || mnemonic == "PUSH"
+                    || mnemonic == "POP"
+            }
+            Self::Branch => &["B", "BL", "BLX"],
+            Self::Swi(_) => &["SVC"],
+            Self::Thumb => return true,
+            Self::ThumbMistaken(_) => &[],
+            Self::Coprocessor | Self::Undefined | Self::Never => &[],
+        };
+
+        mnemonics.contains(&mnemonic)
+    }
+}
+
+/// Checks every instruction in `kmd` against what jimulator can run. Modes are chosen the same
+/// way as for disassembly. SWIs in `host_swis` have a handler on the host side, so aren't issues.
+pub fn compatibility_report(
+    kmd: &[KmdparseToken],
+    default_is_thumb: bool,
+    host_swis: &[u32],
+) -> Vec<CompatibilityIssue> {
+    let source_lines = kmd
+        .iter()
+        .filter_map(|token| match token {
+            KmdparseToken::Line { line } => match (&line.word, line.memory_address) {
+                (Some(KmdparseWord::Instruction { .. }), Some(memory_address)) => {
+                    Some((memory_address, line.comment.trim().to_string()))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut issues = Vec::new();
+
+    for line in disassembly::disassemble_program(kmd, default_is_thumb)
+        .into_iter()
+        .flatten()
+    {
+        let is_thumb = kmd
+            .covering_label(line.memory_address)
+            .map(|label| label.is_thumb)
+            .unwrap_or(default_is_thumb);
+
+        let mut word_bytes = [0; 4];
+        word_bytes[..line.bytes.len()].copy_from_slice(&line.bytes);
+        let word = u32::from_le_bytes(word_bytes);
+
+        let issue = if is_thumb {
+            thumb_issue(word, line.instruction.as_ref(), host_swis)
+        } else {
+            arm_issue(word, line.instruction.as_ref(), host_swis)
+        };
+
+        let Some((kind, explanation)) = issue else {
+            continue;
+        };
+
+        // A Thumb KMD line holds two instructions
+        let source_line = source_lines
+            .range(..=line.memory_address)
+            .next_back()
+            .map(|(_, source_line)| source_line.clone())
+            .unwrap_or_default();
+
+        issues.push(CompatibilityIssue {
+            memory_address: line.memory_address,
+            instruction: line.text,
+            source_line,
+            kind,
+            explanation,
+        });
+    }
+
+    issues
+}
+
+fn arm_issue(
+    word: u32,
+    instruction: Option<&DecodedInstruction>,
+    host_swis: &[u32],
+) -> Option<(CompatibilityIssueKind, String)> {
+    // yaxpeax calls MOVW MOV, which makes no sense in an explanation of why it doesn't work
+    let mnemonic = match word & 0x0FF0_0000 {
+        0x0300_0000 => Some("MOVW"),
+        _ => instruction.map(|instruction| instruction.mnemonic.as_str()),
+    };
+
+    issue(arm_handler(word), instruction, mnemonic, host_swis)
+}
+
+fn thumb_issue(
+    word: u32,
+    instruction: Option<&DecodedInstruction>,
+    host_swis: &[u32],
+) -> Option<(CompatibilityIssueKind, String)> {
+    let is_wide = instruction.is_some_and(|instruction| instruction.length == 4);
+    let mnemonic = instruction.map(|instruction| instruction.mnemonic.as_str());
+
+    // jimulator runs the two halves of BL and BLX as separate instructions, which works out
+    if is_wide && !matches!(mnemonic, Some("BL" | "BLX")) {
+        return Some((
+            CompatibilityIssueKind::Misexecuted,
+            format!(
+                "jimulator only runs 16-bit Thumb instructions (and BL), so the halves of {} are \
+                 run as two separate, unrelated instructions",
+                mnemonic.unwrap_or("this 32-bit instruction")
+            ),
+        ));
+    }
+
+    issue(
+        thumb_handler(word & 0xFFFF),
+        instruction,
+        mnemonic,
+        host_swis,
+    )
+}
+
+/// Turns the handler jimulator would use into an issue, if the handler doesn't implement the
+/// instruction yaxpeax decoded. `mnemonic` is the name to use for the instruction in the
+/// explanation.
+fn issue(
+    handler: Handler,
+    instruction: Option<&DecodedInstruction>,
+    mnemonic: Option<&str>,
+    host_swis: &[u32],
+) -> Option<(CompatibilityIssueKind, String)> {
+    let mnemonic = mnemonic.unwrap_or("this instruction");
+
+    match handler {
+        Handler::Undefined => Some((
+            CompatibilityIssueKind::Undefined,
+            format!(
+                "jimulator only implements ARMv4T instructions (plus CLZ), so {mnemonic} takes the \
+                 undefined instruction exception and jumps to 0x04"
+            ),
+        )),
+
+        Handler::Coprocessor => Some((
+            CompatibilityIssueKind::Undefined,
+            format!(
+                "jimulator has no coprocessors, so {mnemonic} takes the undefined instruction \
+                 exception and jumps to 0x04"
+            ),
+        )),
+
+        Handler::Never => Some((
+            CompatibilityIssueKind::NeverExecuted,
+            format!(
+                "jimulator treats the condition bits 0b1111 as \"never\", so {mnemonic} is skipped"
+            ),
+        )),
+
+        Handler::Swi(number) if !HANDLED_SWIS.contains(&number) && !host_swis.contains(&number) => {
+            Some((
+                CompatibilityIssueKind::UnhandledSwi,
+                format!(
+                    "jimulator only handles SWIs 0 to 4, so SWI {number} jumps to the SWI vector \
+                     at 0x08"
+                ),
+            ))
+        }
+
+        // Words yaxpeax can't decode can't be checked any further
+        _ => match instruction {
+            Some(instruction) if !handler.implements(&instruction.mnemonic) => Some((
+                CompatibilityIssueKind::Misexecuted,
+                format!(
+                    "jimulator doesn't implement {mnemonic}, and runs it as {} instead",
+                    handler.description()
+                ),
+            )),
+            _ => None,
+        },
+    }
+}
+
+/// Follows the ARM half of jimulator's `execute`
+fn arm_handler(word: u32) -> Handler {
+    // BLX with an immediate is the only NV instruction jimulator runs
+    if word >> 28 == 0b1111 && word & 0xFE00_0000 != 0xFA00_0000 {
+        return Handler::Never;
+    }
+
+    match (word >> 25) & 0b111 {
+        0b000 | 0b001 => data_handler(word),
+        0b010 | 0b011 if word & 0x0E00_0010 == 0x0600_0010 => Handler::Undefined,
+        0b010 | 0b011 => Handler::SingleTransfer,
+        0b100 => Handler::BlockTransfer,
+        0b101 => Handler::Branch,
+        0b110 => Handler::Coprocessor,
+        _ if word & 0x0100_0000 == 0 => Handler::Coprocessor,
+        _ => Handler::Swi(word & 0x00FF_FFFF),
+    }
+}
+
+/// Follows jimulator's `dataOp`
+fn data_handler(word: u32) -> Handler {
+    if word & 0x0FC0_00F0 == 0x0000_0090 || word & 0x0F80_00F0 == 0x0080_0090 {
+        return Handler::Multiply;
+    }
+
+    // `isItSBHW` - no multiplies, no signed stores, and register offsets need bits 8-11 clear
+    if word & 0x0E00_0090 == 0x0000_0090
+        && word & 0x0000_0060 != 0
+        && word & 0x0010_0040 != 0x0000_0040
+        && (word & 0x0040_0000 != 0 || word & 0x0000_0F00 == 0)
+    {
+        return Handler::HalfwordTransfer;
+    }
+
+    if word & 0x0FB0_0FF0 == 0x0100_0090 {
+        return Handler::Swap;
+    }
+
+    // TST, TEQ, CMP and CMN without the S bit
+    if word & 0x0190_0000 != 0x0100_0000 {
+        return Handler::DataProcessing;
+    }
+
+    if word & 0x0FBF_0FFF == 0x010F_0000
+        || (word & 0x0DB6_F000 == 0x0120_F000 && word & 0x0200_0010 != 0x0000_0010)
+    {
+        Handler::StatusTransfer
+    } else if word & 0x0FFF_FFD0 == 0x012F_FF10 {
+        Handler::BranchExchange
+    } else if word & 0xFFF0_00F0 == 0xE120_0070 {
+        Handler::Breakpoint
+    } else if word & 0x0FFF_0FF0 == 0x016F_0F10 {
+        Handler::CountLeadingZeros
+    } else {
+        Handler::Undefined
+    }
+}
+
+/// Follows the Thumb half of jimulator's `execute`
+fn thumb_handler(halfword: u32) -> Handler {
+    match halfword >> 8 {
+        // CBZ/CBNZ, the extend and reverse instructions, CPS, IT and the hints
+        0xB1 | 0xB2 | 0xB3 | 0xB6 | 0xB7 | 0xB8 | 0xB9 | 0xBA | 0xBB | 0xBF => Handler::Undefined,
+
+        // UDF, which jimulator takes for a branch with the AL condition
+        0xDE => Handler::ThumbMistaken("a branch"),
+
+        0xDF => Handler::Swi(halfword & 0xFF),
+
+        _ => Handler::Thumb,
+    }
+}
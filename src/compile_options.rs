@@ -0,0 +1,62 @@
+/// The order aasm sorts the symbol table in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, uniffi::Enum)]
+pub enum SymbolOrder {
+    #[default]
+    Alphabetic,
+    Definition,
+    Value,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, uniffi::Record)]
+pub struct SymbolTableOptions {
+    pub order: SymbolOrder,
+
+    /// Whether to include local (numbered) labels (`-sl`)
+    pub include_local_labels: bool,
+
+    /// Whether to include automatically generated literals (`-sp`)
+    pub include_literals: bool,
+}
+
+/// The outputs to ask aasm for on top of the KMD file, which is always generated.
+#[derive(Clone, Debug, Default, PartialEq, Eq, uniffi::Record)]
+pub struct CompileOptions {
+    /// Generate an ELF file (`-e`)
+    pub elf: bool,
+
+    /// Generate a hex dump (`-h`)
+    pub hex: bool,
+
+    /// Generate a symbol table (`-s`)
+    pub symbol_table: Option<SymbolTableOptions>,
+
+    /// Generate a Verilog readmemh file (`-v`)
+    pub verilog: bool,
+
+    /// The size of the Verilog memory image in bytes. aasm's maximum (0x10000) is used if this is
+    /// `None` or bigger than the maximum.
+    pub verilog_size: Option<u32>,
+}
+
+impl SymbolTableOptions {
+    /// The `-s` flag for these options, e.g. `-svlp`
+    pub fn flag(&self) -> String {
+        let mut flag = String::from("-s");
+
+        match self.order {
+            SymbolOrder::Alphabetic => {}
+            SymbolOrder::Definition => flag.push('d'),
+            SymbolOrder::Value => flag.push('v'),
+        }
+
+        if self.include_local_labels {
+            flag.push('l');
+        }
+
+        if self.include_literals {
+            flag.push('p');
+        }
+
+        flag
+    }
+}
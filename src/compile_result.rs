@@ -0,0 +1,57 @@
+use crate::{aasm_diagnostic::AasmDiagnosticSeverity, AasmOutput};
+
+/// The outcome of assembling a file. The full `AasmOutput` is kept in every case so that the
+/// terminal output and diagnostics are always available.
+#[derive(uniffi::Enum)]
+pub enum CompileResult {
+    /// aasm assembled the file without any errors or warnings.
+    Success { output: AasmOutput },
+
+    /// aasm assembled the file, but reported warnings (which are in `output.diagnostics`).
+    SuccessWithWarnings { output: AasmOutput },
+
+    /// aasm failed to assemble the file. `output.kmd` will be empty or partial, so shouldn't be
+    /// loaded.
+    Failure { output: AasmOutput },
+}
+
+impl CompileResult {
+    pub fn output(&self) -> &AasmOutput {
+        match self {
+            Self::Success { output }
+            | Self::SuccessWithWarnings { output }
+            | Self::Failure { output } => output,
+        }
+    }
+
+    pub fn into_output(self) -> AasmOutput {
+        match self {
+            Self::Success { output }
+            | Self::SuccessWithWarnings { output }
+            | Self::Failure { output } => output,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        !matches!(self, Self::Failure { .. })
+    }
+}
+
+impl From<AasmOutput> for CompileResult {
+    fn from(output: AasmOutput) -> Self {
+        if !output.succeeded() {
+            return Self::Failure { output };
+        }
+
+        let has_warnings = output
+            .diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == AasmDiagnosticSeverity::Warning);
+
+        if has_warnings {
+            Self::SuccessWithWarnings { output }
+        } else {
+            Self::Success { output }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use yaxpeax_arm::armv7;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum ConditionCode {
+    Eq,
+    Ne,
+
+    /// Also written `HS`
+    Cs,
+
+    /// Also written `LO`
+    Cc,
+
+    Mi,
+    Pl,
+    Vs,
+    Vc,
+    Hi,
+    Ls,
+    Ge,
+    Lt,
+    Gt,
+    Le,
+    Al,
+}
+
+impl From<armv7::ConditionCode> for ConditionCode {
+    fn from(value: armv7::ConditionCode) -> Self {
+        match value {
+            armv7::ConditionCode::EQ => Self::Eq,
+            armv7::ConditionCode::NE => Self::Ne,
+            armv7::ConditionCode::HS => Self::Cs,
+            armv7::ConditionCode::LO => Self::Cc,
+            armv7::ConditionCode::MI => Self::Mi,
+            armv7::ConditionCode::PL => Self::Pl,
+            armv7::ConditionCode::VS => Self::Vs,
+            armv7::ConditionCode::VC => Self::Vc,
+            armv7::ConditionCode::HI => Self::Hi,
+            armv7::ConditionCode::LS => Self::Ls,
+            armv7::ConditionCode::GE => Self::Ge,
+            armv7::ConditionCode::LT => Self::Lt,
+            armv7::ConditionCode::GT => Self::Gt,
+            armv7::ConditionCode::LE => Self::Le,
+            armv7::ConditionCode::AL => Self::Al,
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    condition_code::ConditionCode,
+    decoded_instruction::{DecodedInstruction, DecodedOperand},
+    disassembly::{self, DisassemblyLine},
+    kmd_extensions::KmdExtensions,
+    kmdparse_types::token::KmdparseToken,
+};
+
+/// The program counter and link register
+const PC: u8 = 15;
+const LR: u8 = 14;
+
+/// The SWI that stops the program in KoMo's SWI handler
+const HALT_SWI: u32 = 2;
+
+/// How control leaves a basic block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum BlockExit {
+    /// The block ends because the next one starts with a label or is branched to
+    FallThrough,
+
+    Branch,
+    ConditionalBranch,
+
+    /// A `BL` (or `BLX`), which comes back to the next block
+    Call,
+
+    /// `MOV PC, LR`, `BX LR` or an `LDM`/`POP` that loads the PC
+    Return,
+
+    /// `SWI 2`
+    Halt,
+
+    /// Any other write to the PC, whose target can't be worked out without running the program
+    Indirect,
+
+    /// The block runs into data or the end of the program
+    End,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum EdgeKind {
+    FallThrough,
+    Branch,
+
+    /// The taken side of a conditional branch. The other side is a `FallThrough` edge.
+    ConditionalBranch,
+
+    Call,
+}
+
+/// A run of instructions that is only ever entered at the top and left at the bottom.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct BasicBlock {
+    pub start_address: u32,
+
+    /// The address just after the last instruction
+    pub end_address: u32,
+
+    /// The label at `start_address`, or the nearest label before it plus an offset (e.g.
+    /// `main+0x10`), or the address itself if there are no labels before it
+    pub name: String,
+
+    /// Every label the KMD defines at `start_address`
+    pub labels: Vec<String>,
+
+    pub lines: Vec<DisassemblyLine>,
+
+    pub exit: BlockExit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct ControlFlowEdge {
+    /// The start address of the block the edge leaves
+    pub from: u32,
+
+    /// The start address of the block the edge goes to
+    pub to: u32,
+
+    pub kind: EdgeKind,
+}
+
+/// The basic blocks of a loaded program and the edges between them.
+#[derive(uniffi::Object)]
+pub struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+    edges: Vec<ControlFlowEdge>,
+}
+
+#[uniffi::export]
+impl ControlFlowGraph {
+    /// The blocks, in address order
+    pub fn blocks(&self) -> Vec<BasicBlock> {
+        self.blocks.clone()
+    }
+
+    pub fn edges(&self) -> Vec<ControlFlowEdge> {
+        self.edges.clone()
+    }
+
+    /// The graph in Graphviz's DOT language. Each block is a box holding its name and disassembly.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph control_flow {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in &self.blocks {
+            let mut label = format!("{}:\\l", escape(&block.name));
+
+            for line in &block.lines {
+                label.push_str(&format!("    {}\\l", escape(&line.text)));
+            }
+
+            dot.push_str(&format!(
+                "    block_{:08X} [label=\"{label}\"];\n",
+                block.start_address
+            ));
+        }
+
+        for edge in &self.edges {
+            let attributes = match edge.kind {
+                EdgeKind::FallThrough => " [style=dashed]",
+                EdgeKind::Branch => "",
+                EdgeKind::ConditionalBranch => " [label=\"taken\"]",
+                EdgeKind::Call => " [style=dotted, label=\"call\"]",
+            };
+
+            dot.push_str(&format!(
+                "    block_{:08X} -> block_{:08X}{attributes};\n",
+                edge.from, edge.to
+            ));
+        }
+
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+/// Where control goes after an instruction
+enum Flow {
+    Next,
+    Branch(u32),
+    Call(Option<u32>),
+    Return,
+    Halt,
+    Indirect,
+}
+
+impl ControlFlowGraph {
+    /// Builds the graph from the instructions in `kmd`. Instructions are decoded as Thumb if their
+    /// covering label is a Thumb label, or if there is no covering label and `default_is_thumb` is
+    /// set.
+    pub fn new(kmd: &[KmdparseToken], default_is_thumb: bool) -> Self {
+        let mut labels = BTreeMap::<u32, Vec<String>>::new();
+
+        for label in kmd.labels() {
+            labels
+                .entry(label.memory_address)
+                .or_default()
+                .push(label.name.clone());
+        }
+
+        let regions = disassembly::disassemble_program(kmd, default_is_thumb);
+
+        // The addresses that have to start a block
+        let mut leaders = labels.keys().copied().collect::<BTreeSet<_>>();
+
+        for region in &regions {
+            leaders.extend(region.first().map(|line| line.memory_address));
+
+            for line in region {
+                let Some(instruction) = &line.instruction else {
+                    continue;
+                };
+
+                let flow = flow(instruction);
+
+                if let Flow::Branch(target) | Flow::Call(Some(target)) = flow {
+                    leaders.insert(target);
+                }
+
+                if !matches!(flow, Flow::Next) {
+                    leaders.insert(line.memory_address.wrapping_add(instruction.length));
+                }
+            }
+        }
+
+        let mut blocks = Vec::new();
+        let mut edges = Vec::new();
+
+        for region in regions {
+            let mut lines = Vec::new();
+            let mut region = region.into_iter().peekable();
+
+            while let Some(line) = region.next() {
+                let flow = line.instruction.as_ref().map(flow).unwrap_or(Flow::Next);
+
+                let is_conditional = line.instruction.as_ref().is_some_and(is_conditional);
+
+                lines.push(line);
+
+                let next = region.peek().map(|line| line.memory_address);
+
+                let ends_block = match next {
+                    Some(next) => !matches!(flow, Flow::Next) || leaders.contains(&next),
+                    None => true,
+                };
+
+                if !ends_block {
+                    continue;
+                }
+
+                let start_address = lines[0].memory_address;
+                let last = &lines[lines.len() - 1];
+                let end_address = last.memory_address.wrapping_add(last.bytes.len() as u32);
+
+                let mut add_edge = |to: u32, kind: EdgeKind| {
+                    edges.push(ControlFlowEdge {
+                        from: start_address,
+                        to,
+                        kind,
+                    })
+                };
+
+                let exit = match flow {
+                    Flow::Next => match next {
+                        Some(_) => BlockExit::FallThrough,
+                        None => BlockExit::End,
+                    },
+                    Flow::Branch(target) if is_conditional => {
+                        add_edge(target, EdgeKind::ConditionalBranch);
+                        BlockExit::ConditionalBranch
+                    }
+                    Flow::Branch(target) => {
+                        add_edge(target, EdgeKind::Branch);
+                        BlockExit::Branch
+                    }
+                    Flow::Call(target) => {
+                        if let Some(target) = target {
+                            add_edge(target, EdgeKind::Call);
+                        }
+
+                        BlockExit::Call
+                    }
+                    Flow::Return => BlockExit::Return,
+                    Flow::Halt => BlockExit::Halt,
+                    Flow::Indirect => BlockExit::Indirect,
+                };
+
+                // Only unconditional branches, returns, halts and jumps never carry on to the
+                // next instruction
+                let falls_through = match flow {
+                    Flow::Next | Flow::Call(_) => true,
+                    Flow::Branch(_) | Flow::Return | Flow::Halt | Flow::Indirect => is_conditional,
+                };
+
+                if let (true, Some(next)) = (falls_through, next) {
+                    add_edge(next, EdgeKind::FallThrough);
+                }
+
+                blocks.push(BasicBlock {
+                    start_address,
+                    end_address,
+                    name: block_name(start_address, &labels),
+                    labels: labels.get(&start_address).cloned().unwrap_or_default(),
+                    lines: std::mem::take(&mut lines),
+                    exit,
+                });
+            }
+        }
+
+        // Branches out of the program (or into data) have nowhere to go
+        let starts = blocks
+            .iter()
+            .map(|block| block.start_address)
+            .collect::<BTreeSet<_>>();
+
+        edges.retain(|edge| starts.contains(&edge.to));
+
+        Self { blocks, edges }
+    }
+}
+
+fn flow(instruction: &DecodedInstruction) -> Flow {
+    let mnemonic = instruction.mnemonic.as_str();
+
+    let loads_pc = instruction.operands.iter().any(|operand| match operand {
+        DecodedOperand::RegisterList { registers } => registers.contains(&PC),
+        _ => false,
+    });
+
+    if let Some(target) = instruction.branch_target {
+        return match mnemonic {
+            "BL" | "BLX" => Flow::Call(Some(target)),
+            _ => Flow::Branch(target),
+        };
+    }
+
+    match (mnemonic, instruction.operands.as_slice()) {
+        // `BLX Rm`
+        ("BL" | "BLX", _) => Flow::Call(None),
+
+        ("BX", [DecodedOperand::Register { register: LR }])
+        | (
+            "MOV",
+            [DecodedOperand::Register { register: PC }, DecodedOperand::Register { register: LR }],
+        ) => Flow::Return,
+
+        (_, _) if loads_pc && (mnemonic.starts_with("LDM") || mnemonic == "POP") => Flow::Return,
+
+        ("SVC", [DecodedOperand::Immediate { value: HALT_SWI }]) => Flow::Halt,
+
+        (_, _) if instruction.registers_written.contains(&PC) => Flow::Indirect,
+
+        _ => Flow::Next,
+    }
+}
+
+/// Whether the instruction might not happen. `CBZ`/`CBNZ` test a register rather than having a
+/// condition code.
+fn is_conditional(instruction: &DecodedInstruction) -> bool {
+    instruction.condition != ConditionCode::Al
+        || matches!(instruction.mnemonic.as_str(), "CBZ" | "CBNZ")
+}
+
+fn block_name(address: u32, labels: &BTreeMap<u32, Vec<String>>) -> String {
+    match labels.range(..=address).next_back() {
+        Some((label_address, names)) if *label_address == address => names[0].clone(),
+        Some((label_address, names)) => format!("{}+0x{:X}", names[0], address - label_address),
+        None => format!("0x{address:08X}"),
+    }
+}
+
+/// Escapes text for a DOT string
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmdparse_types::{label::KmdparseLabel, line::KmdparseLine, word::KmdparseWord};
+
+    /// `main CMP r0, #0 / BEQ done / BL func / MOVNE pc, lr / B main / done SWI 2 /
+    /// func MOV pc, lr / func2 LDMFD sp!, {r4, pc}`, as aasm assembles it
+    const PROGRAM: [(u32, u32); 8] = [
+        (0x00, 0xE350_0000),
+        (0x04, 0x0A00_0002),
+        (0x08, 0xEB00_0002),
+        (0x0C, 0x11A0_F00E),
+        (0x10, 0xEAFF_FFFA),
+        (0x14, 0xEF00_0002),
+        (0x18, 0xE1A0_F00E),
+        (0x1C, 0xE8BD_8010),
+    ];
+
+    fn kmd(words: &[(u32, u32)], labels: &[(&str, u32)]) -> Vec<KmdparseToken> {
+        let lines = words
+            .iter()
+            .map(|(memory_address, word)| KmdparseToken::Line {
+                line: KmdparseLine {
+                    memory_address: Some(*memory_address),
+                    word: Some(KmdparseWord::Instruction {
+                        instruction: word.to_le_bytes(),
+                    }),
+                    comment: String::new(),
+                },
+            });
+
+        let labels = labels
+            .iter()
+            .map(|(name, memory_address)| KmdparseToken::Label {
+                label: KmdparseLabel {
+                    name: name.to_string(),
+                    memory_address: *memory_address,
+                    is_exported: false,
+                    is_thumb: false,
+                },
+            });
+
+        lines.chain(labels).collect()
+    }
+
+    fn graph() -> ControlFlowGraph {
+        ControlFlowGraph::new(
+            &kmd(
+                &PROGRAM,
+                &[
+                    ("main", 0x00),
+                    ("done", 0x14),
+                    ("func", 0x18),
+                    ("func2", 0x1C),
+                ],
+            ),
+            false,
+        )
+    }
+
+    #[test]
+    fn splits_blocks_at_labels_and_exits() {
+        let blocks = graph()
+            .blocks()
+            .into_iter()
+            .map(|block| {
+                (
+                    block.start_address,
+                    block.end_address,
+                    block.name,
+                    block.exit,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            blocks,
+            vec![
+                (
+                    0x00,
+                    0x08,
+                    String::from("main"),
+                    BlockExit::ConditionalBranch
+                ),
+                (0x08, 0x0C, String::from("main+0x8"), BlockExit::Call),
+                (0x0C, 0x10, String::from("main+0xC"), BlockExit::Return),
+                (0x10, 0x14, String::from("main+0x10"), BlockExit::Branch),
+                (0x14, 0x18, String::from("done"), BlockExit::Halt),
+                (0x18, 0x1C, String::from("func"), BlockExit::Return),
+                (0x1C, 0x20, String::from("func2"), BlockExit::Return),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_conditional_exits_and_calls_fall_through() {
+        let edge = |from, to, kind| ControlFlowEdge { from, to, kind };
+
+        assert_eq!(
+            graph().edges(),
+            vec![
+                edge(0x00, 0x14, EdgeKind::ConditionalBranch),
+                edge(0x00, 0x08, EdgeKind::FallThrough),
+                edge(0x08, 0x18, EdgeKind::Call),
+                edge(0x08, 0x0C, EdgeKind::FallThrough),
+                edge(0x0C, 0x10, EdgeKind::FallThrough),
+                edge(0x10, 0x00, EdgeKind::Branch),
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_edges_out_of_the_program() {
+        // B 0x100
+        let graph = ControlFlowGraph::new(&kmd(&[(0x00, 0xEA00_003E)], &[]), false);
+
+        assert_eq!(graph.blocks()[0].name, "0x00000000");
+        assert_eq!(graph.blocks()[0].exit, BlockExit::Branch);
+        assert!(graph.edges().is_empty());
+    }
+
+    #[test]
+    fn escapes_dot_strings() {
+        // SWI 2 / B ., under a label with a quote and a backslash in it
+        let graph = ControlFlowGraph::new(
+            &kmd(
+                &[(0x00, 0xEF00_0002), (0x04, 0xEAFF_FFFE)],
+                &[("a\"b\\c", 0x00)],
+            ),
+            false,
+        );
+
+        assert_eq!(
+            graph.to_dot(),
+            r#"digraph control_flow {
+    node [shape=box, fontname="monospace"];
+    block_00000000 [label="a\"b\\c:\l    SWI 0x2\l"];
+    block_00000004 [label="a\"b\\c+0x4:\l    B 0x00000004\l"];
+    block_00000004 -> block_00000004;
+}
+"#
+        );
+    }
+}
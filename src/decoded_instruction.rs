@@ -0,0 +1,610 @@
+use yaxpeax_arch::{Colorize, NoColors};
+use yaxpeax_arm::armv7::{Instruction, Opcode, Operand, Reg, RegShift, RegShiftStyle, ShiftStyle};
+
+use crate::condition_code::ConditionCode;
+
+/// The program counter, link register and stack pointer
+const PC: u8 = 15;
+const LR: u8 = 14;
+const SP: u8 = 13;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum ShiftKind {
+    Lsl,
+    Lsr,
+    Asr,
+    Ror,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum ShiftAmount {
+    Immediate { amount: u8 },
+    Register { register: u8 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct Shift {
+    pub kind: ShiftKind,
+    pub amount: ShiftAmount,
+}
+
+/// What gets added to (or subtracted from) the base register of a memory operand.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum MemoryOffset {
+    None,
+    Immediate { value: u32 },
+    Register { register: u8 },
+    ShiftedRegister { register: u8, shift: Shift },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum DecodedOperand {
+    Register {
+        register: u8,
+    },
+
+    /// A base register that may be written back to, like the `R13!` in `STMFD R13!, {R0}`
+    RegisterWriteback {
+        register: u8,
+        writeback: bool,
+    },
+
+    RegisterList {
+        registers: Vec<u8>,
+    },
+
+    ShiftedRegister {
+        register: u8,
+        shift: Shift,
+    },
+
+    Memory {
+        base: u8,
+        offset: MemoryOffset,
+
+        /// Whether the offset is subtracted from the base rather than added
+        subtract: bool,
+
+        /// Whether the offset is applied before the access (`[R0, #4]`) or after it (`[R0], #4`)
+        is_preindexed: bool,
+
+        /// Whether the base register is updated. This is always true for post-indexed operands.
+        writeback: bool,
+    },
+
+    Immediate {
+        value: u32,
+    },
+
+    /// A PC-relative branch offset in bytes, measured from the address of the instruction itself
+    BranchOffset {
+        offset: i32,
+    },
+
+    /// Anything else (coprocessor and status register operands), as yaxpeax displays it
+    Other {
+        text: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum MemoryAccessKind {
+    Load,
+    Store,
+
+    /// `SWP`, which loads and stores
+    Swap,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct MemoryAccess {
+    pub kind: MemoryAccessKind,
+
+    /// The number of bytes transferred. For `LDM`/`STM`, this is 4 bytes for each register.
+    pub size: u32,
+}
+
+/// An ARM or Thumb instruction, broken down into its parts.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct DecodedInstruction {
+    /// The whole instruction, as returned by `decode_instruction`
+    pub text: String,
+
+    /// The opcode, without the condition or `S` suffix (e.g. `ADD` for `ADDEQS`). `LDM`/`STM`
+    /// always include their addressing mode, e.g. `LDMIA`.
+    pub mnemonic: String,
+
+    pub condition: ConditionCode,
+
+    /// Whether the instruction updates the condition flags, either because of an `S` suffix or
+    /// because it is a comparison
+    pub sets_flags: bool,
+
+    pub operands: Vec<DecodedOperand>,
+
+    /// The registers the instruction reads, in ascending order
+    pub registers_read: Vec<u8>,
+
+    /// The registers the instruction writes, in ascending order. Branches write the PC (and the LR
+    /// for branches with link).
+    pub registers_written: Vec<u8>,
+
+    /// The memory the instruction loads or stores, if any
+    pub memory_access: Option<MemoryAccess>,
+
+    /// Where the instruction branches to, if that can be worked out without running it
+    pub branch_target: Option<u32>,
+
+    /// The address an `ADR` (or an `ADD`/`SUB` of an immediate from the PC) puts in its
+    /// destination register
+    pub adr_target: Option<u32>,
+
+    /// The length of the instruction in bytes (2 for 16-bit Thumb instructions, 4 otherwise)
+    pub length: u32,
+}
+
+impl DecodedInstruction {
+    /// Builds a `DecodedInstruction` from a yaxpeax `Instruction` that was decoded at `address`.
+    pub fn new(instruction: &Instruction, address: u32) -> Self {
+        let operands = instruction
+            .operands
+            .iter()
+            .filter(|operand| **operand != Operand::Nothing)
+            .map(|operand| decode_operand(instruction, operand))
+            .collect::<Vec<_>>();
+
+        let (registers_read, registers_written) = register_usage(instruction.opcode, &operands);
+
+        let branch_target = operands.iter().find_map(|operand| match operand {
+            DecodedOperand::BranchOffset { offset } => {
+                let target = address.wrapping_add_signed(*offset);
+
+                // BLX from Thumb switches to ARM, so the target is word aligned
+                Some(match instruction.opcode {
+                    Opcode::BLX if instruction.thumb => target & !0b11,
+                    _ => target,
+                })
+            }
+            _ => None,
+        });
+
+        let adr_target = adr_target(instruction, &operands, address);
+
+        Self {
+            text: instruction.to_string().to_uppercase(),
+            mnemonic: mnemonic(instruction.opcode),
+            condition: ConditionCode::from(instruction.condition),
+            sets_flags: instruction.s
+                || matches!(
+                    instruction.opcode,
+                    Opcode::TST | Opcode::TEQ | Opcode::CMP | Opcode::CMN
+                ),
+            memory_access: memory_access(instruction.opcode, &operands),
+            operands,
+            registers_read,
+            registers_written,
+            branch_target,
+            adr_target,
+            length: if instruction.thumb && !instruction.wide {
+                2
+            } else {
+                4
+            },
+        }
+    }
+}
+
+/// yaxpeax displays some `LDM`/`STM` addressing modes wrongly, so those are worked out from the
+/// opcode's flags.
+fn mnemonic(opcode: Opcode) -> String {
+    let mode = |add: bool, pre: bool| match (add, pre) {
+        (true, false) => "IA",
+        (true, true) => "IB",
+        (false, false) => "DA",
+        (false, true) => "DB",
+    };
+
+    match opcode {
+        Opcode::LDM(add, pre, _, _) => format!("LDM{}", mode(add, pre)),
+        Opcode::STM(add, pre, _, _) => format!("STM{}", mode(add, pre)),
+        _ => opcode.to_string().to_uppercase(),
+    }
+}
+
+fn decode_operand(instruction: &Instruction, operand: &Operand) -> DecodedOperand {
+    match *operand {
+        Operand::Reg(register) => DecodedOperand::Register {
+            register: register.number(),
+        },
+        Operand::RegWBack(register, writeback) => DecodedOperand::RegisterWriteback {
+            register: register.number(),
+            writeback,
+        },
+        Operand::RegList(list) => DecodedOperand::RegisterList {
+            registers: (0..16).filter(|bit| list & (1 << bit) != 0).collect(),
+        },
+        Operand::RegShift(reg_shift) => {
+            let (register, shift) = decode_shift(reg_shift);
+
+            DecodedOperand::ShiftedRegister { register, shift }
+        }
+        Operand::RegDeref(base) => memory(base, MemoryOffset::None, true, true, false),
+        Operand::RegDerefPreindexOffset(base, offset, add, writeback) => memory(
+            base,
+            MemoryOffset::Immediate {
+                value: offset.into(),
+            },
+            add,
+            true,
+            writeback,
+        ),
+        Operand::RegDerefPostindexOffset(base, offset, add, _) => memory(
+            base,
+            MemoryOffset::Immediate {
+                value: offset.into(),
+            },
+            add,
+            false,
+            true,
+        ),
+        Operand::RegDerefPreindexReg(base, offset, add, writeback) => memory(
+            base,
+            MemoryOffset::Register {
+                register: offset.number(),
+            },
+            add,
+            true,
+            writeback,
+        ),
+        Operand::RegDerefPostindexReg(base, offset, add, _) => memory(
+            base,
+            MemoryOffset::Register {
+                register: offset.number(),
+            },
+            add,
+            false,
+            true,
+        ),
+        Operand::RegDerefPreindexRegShift(base, reg_shift, add, writeback) => {
+            let (register, shift) = decode_shift(reg_shift);
+
+            memory(
+                base,
+                MemoryOffset::ShiftedRegister { register, shift },
+                add,
+                true,
+                writeback,
+            )
+        }
+        Operand::RegDerefPostindexRegShift(base, reg_shift, add, _) => {
+            let (register, shift) = decode_shift(reg_shift);
+
+            memory(
+                base,
+                MemoryOffset::ShiftedRegister { register, shift },
+                add,
+                false,
+                true,
+            )
+        }
+        Operand::Imm12(value) => DecodedOperand::Immediate {
+            value: value.into(),
+        },
+        Operand::Imm32(value) => DecodedOperand::Immediate { value },
+
+        // yaxpeax already includes the 8 byte pipeline offset for ARM branches
+        Operand::BranchOffset(offset) => DecodedOperand::BranchOffset {
+            offset: offset.wrapping_mul(4),
+        },
+
+        // For Thumb branches, yaxpeax adds one halfword (of the 4 byte pipeline offset) for 16-bit
+        // conditional branches and CBZ/CBNZ, and nothing for everything else
+        Operand::BranchThumbOffset(offset) => {
+            let is_biased = matches!(instruction.opcode, Opcode::CBZ | Opcode::CBNZ)
+                || (instruction.opcode == Opcode::B
+                    && !instruction.wide
+                    && is_conditional(instruction));
+
+            let halfwords = if is_biased { offset + 1 } else { offset + 2 };
+
+            DecodedOperand::BranchOffset {
+                offset: halfwords.wrapping_mul(2),
+            }
+        }
+        _ => {
+            let mut text = String::new();
+
+            // Writing to a String can't fail
+            let _ = operand.colorize(&NoColors, &mut text);
+
+            DecodedOperand::Other {
+                text: text.to_uppercase(),
+            }
+        }
+    }
+}
+
+fn adr_target(instruction: &Instruction, operands: &[DecodedOperand], address: u32) -> Option<u32> {
+    use DecodedOperand::{Immediate, Register};
+
+    match (instruction.opcode, operands) {
+        // Thumb ADR is relative to the word aligned PC
+        (Opcode::ADR, [Register { .. }, Immediate { value }]) => {
+            Some((address.wrapping_add(4) & !0b11).wrapping_add(*value))
+        }
+
+        (
+            Opcode::ADR | Opcode::ADD | Opcode::SUB,
+            [Register { .. }, Register { register: PC }, Immediate { value }],
+        ) => {
+            let pc = address.wrapping_add(if instruction.thumb { 4 } else { 8 });
+
+            Some(match instruction.opcode {
+                Opcode::SUB => pc.wrapping_sub(*value),
+                _ => pc.wrapping_add(*value),
+            })
+        }
+
+        _ => None,
+    }
+}
+
+fn is_conditional(instruction: &Instruction) -> bool {
+    ConditionCode::from(instruction.condition) != ConditionCode::Al
+}
+
+fn memory(
+    base: Reg,
+    offset: MemoryOffset,
+    add: bool,
+    is_preindexed: bool,
+    writeback: bool,
+) -> DecodedOperand {
+    DecodedOperand::Memory {
+        base: base.number(),
+        offset,
+        subtract: !add,
+        is_preindexed,
+        writeback,
+    }
+}
+
+fn decode_shift(reg_shift: RegShift) -> (u8, Shift) {
+    let kind = |style: ShiftStyle| match style {
+        ShiftStyle::LSL => ShiftKind::Lsl,
+        ShiftStyle::LSR => ShiftKind::Lsr,
+        ShiftStyle::ASR => ShiftKind::Asr,
+        ShiftStyle::ROR => ShiftKind::Ror,
+    };
+
+    match reg_shift.into_shift() {
+        RegShiftStyle::RegImm(shift) => (
+            shift.shiftee().number(),
+            Shift {
+                kind: kind(shift.stype()),
+                amount: ShiftAmount::Immediate {
+                    amount: shift.imm(),
+                },
+            },
+        ),
+        RegShiftStyle::RegReg(shift) => (
+            shift.shiftee().number(),
+            Shift {
+                kind: kind(shift.stype()),
+                amount: ShiftAmount::Register {
+                    register: shift.shifter().number(),
+                },
+            },
+        ),
+    }
+}
+
+/// The number of operands at the start of the instruction that are written rather than read.
+fn destination_count(opcode: Opcode) -> usize {
+    match opcode {
+        Opcode::TST
+        | Opcode::TEQ
+        | Opcode::CMP
+        | Opcode::CMN
+        | Opcode::STR
+        | Opcode::STRB
+        | Opcode::STRH
+        | Opcode::STRD
+        | Opcode::STRT
+        | Opcode::STRBT
+        | Opcode::STRHT
+        | Opcode::STM(..)
+        | Opcode::LDM(..)
+        | Opcode::PUSH
+        | Opcode::POP
+        | Opcode::B
+        | Opcode::BL
+        | Opcode::BLX
+        | Opcode::BX
+        | Opcode::BXJ
+        | Opcode::CBZ
+        | Opcode::CBNZ
+        | Opcode::TBB
+        | Opcode::TBH
+        | Opcode::MSR
+        | Opcode::SVC
+        | Opcode::BKPT
+        | Opcode::UDF
+        | Opcode::NOP
+        | Opcode::PLD
+        | Opcode::PLI
+        | Opcode::IT
+        | Opcode::LDC(_)
+        | Opcode::LDCL(_)
+        | Opcode::LDC2(_)
+        | Opcode::LDC2L(_)
+        | Opcode::STC(_)
+        | Opcode::STCL(_)
+        | Opcode::STC2(_)
+        | Opcode::STC2L(_)
+        | Opcode::MCR2(..)
+        | Opcode::MCRR(..)
+        | Opcode::MCRR2(..)
+        | Opcode::CDP2(..)
+        | Opcode::SRS(..)
+        | Opcode::RFE(..)
+        | Opcode::SETEND
+        | Opcode::CPS(_)
+        | Opcode::CPS_modeonly => 0,
+
+        Opcode::UMULL
+        | Opcode::UMLAL
+        | Opcode::SMULL
+        | Opcode::SMLAL
+        | Opcode::SMLAL_halfword(..)
+        | Opcode::SMLALD(_)
+        | Opcode::SMLSLD(_)
+        | Opcode::UMAAL
+        | Opcode::LDRD
+        | Opcode::LDREXD
+        | Opcode::MRRC(..)
+        | Opcode::MRRC2(..) => 2,
+
+        _ => 1,
+    }
+}
+
+/// Whether the instruction reads its destinations as well as writing them (e.g. `UMLAL`, which
+/// accumulates into them).
+fn reads_destinations(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::UMLAL
+            | Opcode::SMLAL
+            | Opcode::SMLAL_halfword(..)
+            | Opcode::SMLALD(_)
+            | Opcode::SMLSLD(_)
+            | Opcode::UMAAL
+            | Opcode::MOVT
+            | Opcode::BFI
+            | Opcode::BFC
+    )
+}
+
+fn register_usage(opcode: Opcode, operands: &[DecodedOperand]) -> (Vec<u8>, Vec<u8>) {
+    let mut read = Vec::new();
+    let mut written = Vec::new();
+
+    let destination_count = destination_count(opcode);
+
+    for (index, operand) in operands.iter().enumerate() {
+        let is_destination = index < destination_count;
+
+        match operand {
+            DecodedOperand::Register { register } => {
+                if is_destination {
+                    written.push(*register);
+                }
+
+                if !is_destination || reads_destinations(opcode) {
+                    read.push(*register);
+                }
+            }
+            DecodedOperand::RegisterWriteback {
+                register,
+                writeback,
+            } => {
+                read.push(*register);
+
+                if *writeback {
+                    written.push(*register);
+                }
+            }
+            DecodedOperand::RegisterList { registers } => match opcode {
+                Opcode::LDM(..) | Opcode::POP => written.extend(registers),
+                _ => read.extend(registers),
+            },
+            DecodedOperand::ShiftedRegister { register, shift } => {
+                read.push(*register);
+
+                if let ShiftAmount::Register { register } = shift.amount {
+                    read.push(register);
+                }
+            }
+            DecodedOperand::Memory {
+                base,
+                offset,
+                writeback,
+                ..
+            } => {
+                read.push(*base);
+
+                if *writeback {
+                    written.push(*base);
+                }
+
+                match offset {
+                    MemoryOffset::Register { register } => read.push(*register),
+                    MemoryOffset::ShiftedRegister { register, shift } => {
+                        read.push(*register);
+
+                        if let ShiftAmount::Register { register } = shift.amount {
+                            read.push(register);
+                        }
+                    }
+                    MemoryOffset::None | MemoryOffset::Immediate { .. } => {}
+                }
+            }
+            DecodedOperand::Immediate { .. }
+            | DecodedOperand::BranchOffset { .. }
+            | DecodedOperand::Other { .. } => {}
+        }
+    }
+
+    match opcode {
+        Opcode::B | Opcode::BX | Opcode::BXJ | Opcode::CBZ | Opcode::CBNZ => written.push(PC),
+        Opcode::BL | Opcode::BLX => written.extend([PC, LR]),
+        Opcode::PUSH | Opcode::POP => {
+            read.push(SP);
+            written.push(SP);
+        }
+        _ => {}
+    }
+
+    read.sort_unstable();
+    read.dedup();
+    written.sort_unstable();
+    written.dedup();
+
+    (read, written)
+}
+
+fn memory_access(opcode: Opcode, operands: &[DecodedOperand]) -> Option<MemoryAccess> {
+    let register_count = operands
+        .iter()
+        .find_map(|operand| match operand {
+            DecodedOperand::RegisterList { registers } => Some(registers.len() as u32),
+            _ => None,
+        })
+        .unwrap_or(1);
+
+    let (kind, size) = match opcode {
+        Opcode::LDR | Opcode::LDRT | Opcode::LDREX => (MemoryAccessKind::Load, 4),
+        Opcode::LDRB | Opcode::LDRBT | Opcode::LDREXB | Opcode::LDRSB | Opcode::LDRSBT => {
+            (MemoryAccessKind::Load, 1)
+        }
+        Opcode::LDRH | Opcode::LDRHT | Opcode::LDREXH | Opcode::LDRSH | Opcode::LDRSHT => {
+            (MemoryAccessKind::Load, 2)
+        }
+        Opcode::LDRD | Opcode::LDREXD => (MemoryAccessKind::Load, 8),
+        Opcode::LDM(..) | Opcode::POP => (MemoryAccessKind::Load, 4 * register_count),
+
+        Opcode::STR | Opcode::STRT | Opcode::STREX => (MemoryAccessKind::Store, 4),
+        Opcode::STRB | Opcode::STRBT | Opcode::STREXB => (MemoryAccessKind::Store, 1),
+        Opcode::STRH | Opcode::STRHT | Opcode::STREXH => (MemoryAccessKind::Store, 2),
+        Opcode::STRD | Opcode::STREXD => (MemoryAccessKind::Store, 8),
+        Opcode::STM(..) | Opcode::PUSH => (MemoryAccessKind::Store, 4 * register_count),
+
+        Opcode::SWP => (MemoryAccessKind::Swap, 4),
+        Opcode::SWPB => (MemoryAccessKind::Swap, 1),
+
+        _ => return None,
+    };
+
+    Some(MemoryAccess { kind, size })
+}
@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    arm_decoder::{decode_instruction_details, decode_instruction_details_thumb},
+    decoded_instruction::DecodedInstruction,
+    disassembly_options::{DisassemblyOptions, ImmediateBase},
+    instruction_formatter,
+    kmd_extensions::KmdExtensions,
+    kmdparse_types::{token::KmdparseToken, word::KmdparseWord},
+};
+
+/// One line of a disassembly listing - either an instruction or some data.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct DisassemblyLine {
+    pub memory_address: u32,
+
+    /// The bytes this line covers, as they currently are in memory
+    pub bytes: Vec<u8>,
+
+    /// The labels the KMD defines at this address
+    pub labels: Vec<String>,
+
+    /// The instruction (or `DEFB`/`DEFW` for data), written out as the `DisassemblyOptions` say,
+    /// with branch and `ADR` targets replaced by labels where there are any
+    pub text: String,
+
+    /// The decoded instruction, or `None` for data and words that couldn't be decoded
+    pub instruction: Option<DecodedInstruction>,
+
+    /// Whether the KMD says this is data rather than an instruction
+    pub is_data: bool,
+}
+
+/// Disassembles `memory`, which holds the memory from `start` up to (not including) `end`, plus a
+/// few bytes of slack so that an instruction that starts just before `end` can be decoded. Lines
+/// are decoded as Thumb if their covering label in `kmd` is a Thumb label, or if there is no
+/// covering label and `default_is_thumb` is set.
+pub fn disassemble(
+    memory: &[u8],
+    start: u32,
+    end: u32,
+    kmd: &[KmdparseToken],
+    default_is_thumb: bool,
+    options: &DisassemblyOptions,
+) -> Vec<DisassemblyLine> {
+    let data_ranges = kmd.data_ranges();
+
+    let mut labels = BTreeMap::<u32, Vec<String>>::new();
+
+    for label in kmd.labels() {
+        labels
+            .entry(label.memory_address)
+            .or_default()
+            .push(label.name.clone());
+    }
+
+    let target_name = |target: u32| {
+        labels
+            .get(&target)
+            .and_then(|labels| labels.first())
+            .cloned()
+    };
+
+    let bytes_at = |address: u32, length: u32| {
+        let offset = address.wrapping_sub(start) as usize;
+
+        memory
+            .get(offset..(offset + length as usize).min(memory.len()))
+            .unwrap_or_default()
+            .to_vec()
+    };
+
+    let mut lines = Vec::new();
+    let mut address = start;
+
+    while address < end {
+        let line_labels = labels.get(&address).cloned().unwrap_or_default();
+
+        // Data lines are shown from where the KMD line starts, even if `start` is part way in
+        let data_range = data_ranges
+            .range(..=address)
+            .next_back()
+            .filter(|(data_start, length)| data_start.wrapping_add(**length) > address);
+
+        if let Some((data_start, length)) = data_range {
+            let length = data_start.wrapping_add(*length) - address;
+            let bytes = bytes_at(address, length);
+
+            lines.push(DisassemblyLine {
+                memory_address: address,
+                text: data_text(address, &bytes, options),
+                bytes,
+                labels: line_labels,
+                instruction: None,
+                is_data: true,
+            });
+
+            // Stop rather than wrap around to the start of the address space
+            match address.checked_add(length) {
+                Some(next_address) => address = next_address,
+                None => break,
+            }
+
+            continue;
+        }
+
+        let is_thumb = kmd
+            .covering_label(address)
+            .map(|label| label.is_thumb)
+            .unwrap_or(default_is_thumb);
+
+        let mut word_bytes = [0; 4];
+
+        for (index, byte) in bytes_at(address, 4).into_iter().enumerate() {
+            word_bytes[index] = byte;
+        }
+
+        let word = u32::from_le_bytes(word_bytes);
+
+        let decoded = if is_thumb {
+            decode_instruction_details_thumb(word, address)
+        } else {
+            decode_instruction_details(word, address)
+        };
+
+        let line = match decoded {
+            Ok(instruction) => DisassemblyLine {
+                memory_address: address,
+                bytes: bytes_at(address, instruction.length),
+                labels: line_labels,
+                text: instruction_formatter::format(&instruction, options, &target_name),
+                instruction: Some(instruction),
+                is_data: false,
+            },
+            Err(_) => {
+                let bytes = bytes_at(address, if is_thumb { 2 } else { 4 });
+
+                DisassemblyLine {
+                    memory_address: address,
+                    text: data_text(address, &bytes, options),
+                    bytes,
+                    labels: line_labels,
+                    instruction: None,
+                    is_data: false,
+                }
+            }
+        };
+
+        // Always move on, even if memory ran out and there are no bytes
+        let line_length = (line.bytes.len() as u32).max(2);
+
+        lines.push(line);
+
+        // Stop rather than wrap around to the start of the address space
+        match address.checked_add(line_length) {
+            Some(next_address) => address = next_address,
+            None => break,
+        }
+    }
+
+    lines
+}
+
+/// Disassembles each run of consecutive instruction lines in `kmd`, as they were loaded. Modes are
+/// chosen the same way as in [`disassemble`].
+pub fn disassemble_program(
+    kmd: &[KmdparseToken],
+    default_is_thumb: bool,
+) -> Vec<Vec<DisassemblyLine>> {
+    let mut ranges = Vec::<(u32, u32)>::new();
+
+    let instruction_addresses = kmd.iter().filter_map(|token| match token {
+        KmdparseToken::Line { line } => match (&line.word, line.memory_address) {
+            (Some(KmdparseWord::Instruction { .. }), Some(memory_address)) => Some(memory_address),
+            _ => None,
+        },
+        _ => None,
+    });
+
+    for address in instruction_addresses.collect::<BTreeSet<_>>() {
+        match ranges.last_mut() {
+            Some((_, end)) if *end == address => *end = address.wrapping_add(4),
+            _ => ranges.push((address, address.wrapping_add(4))),
+        }
+    }
+
+    let image = kmd.image();
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            // The extra 2 bytes let a 32-bit Thumb instruction at the end of the range be decoded
+            let memory = (0..end.wrapping_sub(start).wrapping_add(2))
+                .map(|offset| {
+                    image
+                        .get(&start.wrapping_add(offset))
+                        .copied()
+                        .unwrap_or_default()
+                })
+                .collect::<Vec<_>>();
+
+            disassemble(
+                &memory,
+                start,
+                end,
+                kmd,
+                default_is_thumb,
+                &DisassemblyOptions::default(),
+            )
+        })
+        .collect()
+}
+
+/// `DEFW` for aligned words, `DEFB` for anything else.
+fn data_text(address: u32, bytes: &[u8], options: &DisassemblyOptions) -> String {
+    let text = match <[u8; 4]>::try_from(bytes) {
+        Ok(word) if address & 0b11 == 0 => {
+            let word = u32::from_le_bytes(word);
+
+            match options.immediate_base {
+                ImmediateBase::Hexadecimal => format!("DEFW 0x{word:08X}"),
+                ImmediateBase::Decimal => format!("DEFW {word}"),
+            }
+        }
+        _ => {
+            let bytes = bytes
+                .iter()
+                .map(|byte| match options.immediate_base {
+                    ImmediateBase::Hexadecimal => format!("0x{byte:02X}"),
+                    ImmediateBase::Decimal => byte.to_string(),
+                })
+                .collect::<Vec<_>>();
+
+            format!("DEFB {}", bytes.join(", "))
+        }
+    };
+
+    if options.lower_case {
+        text.to_lowercase()
+    } else {
+        text
+    }
+}
@@ -0,0 +1,46 @@
+/// The assembly syntax disassembled instructions are written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, uniffi::Enum)]
+pub enum DisassemblySyntax {
+    /// The pre-UAL syntax aasm and KoMo use, e.g. `LDMEQFD SP!, {R0-R3}` and `SWI 2`
+    #[default]
+    Komo,
+
+    /// ARM's Unified Assembler Language, e.g. `LDMEQ SP!, {R0-R3}` and `SVC #2`
+    Ual,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, uniffi::Enum)]
+pub enum ImmediateBase {
+    #[default]
+    Hexadecimal,
+    Decimal,
+}
+
+/// How instructions are written out by the disassembler.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct DisassemblyOptions {
+    pub syntax: DisassemblySyntax,
+
+    /// Write mnemonics, registers and numbers in lower case. Labels are always written as they are
+    /// in the KMD.
+    pub lower_case: bool,
+
+    /// The base for immediates, offsets and data. Shift amounts are always decimal.
+    pub immediate_base: ImmediateBase,
+
+    /// Write instructions the way they were probably written, e.g. `ADR R0, msg` rather than
+    /// `ADD R0, PC, #0x10`, `PUSH {R4, LR}` rather than `STMFD SP!, {R4, LR}`, and `NOP` rather
+    /// than `MOV R0, R0`
+    pub recover_pseudo_ops: bool,
+}
+
+impl Default for DisassemblyOptions {
+    fn default() -> Self {
+        Self {
+            syntax: DisassemblySyntax::default(),
+            lower_case: false,
+            immediate_base: ImmediateBase::default(),
+            recover_pseudo_ops: true,
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use std::{array::TryFromSliceError, io, num::TryFromIntError, str, string::FromUtf8Error};
 use thiserror::Error;
 
+use crate::arm_decoder::DecoderError;
+
 #[derive(Debug, Error, uniffi::Error)]
 #[uniffi(flat_error)]
 pub enum LibiguanaError {
@@ -46,4 +48,71 @@ pub enum LibiguanaError {
 
     #[error("A trap was not defined for address {0:#08x}")]
     NoTrapForAddress(u32),
+
+    #[error("No kmd file has been loaded")]
+    NoKmdLoaded,
+
+    #[error("{0} is not a valid register number")]
+    InvalidRegister(u8),
+
+    #[error("Line {0} of the mnemonics file could not be parsed")]
+    MnemonicsParseError(u32),
+
+    #[error("aasm failed to assemble the source:\n{0}")]
+    AssemblyFailed(String),
+
+    #[error("The patch did not assemble to a single instruction at {0:#08x}")]
+    PatchNotSingleInstruction(u32),
+
+    #[error("{0} is not a valid terminal device")]
+    InvalidTerminal(u8),
+
+    #[error("Nothing reads input sent to terminal {0}")]
+    TerminalNotWritable(u8),
+
+    #[error("Expected {expected:?}, but the program {reason} after writing {output:?}")]
+    ExpectFailed {
+        expected: String,
+        reason: String,
+        output: String,
+    },
+
+    #[error("The program did not finish, it {reason} after writing {output:?}")]
+    ProgramDidNotFinish { reason: String, output: String },
+
+    #[error("The program has no label called {0}")]
+    NoSuchLabel(String),
+
+    #[error("The program is running")]
+    ProgramRunning,
+
+    #[error("The function did not return, the program {0}")]
+    FunctionDidNotReturn(String),
+
+    #[error("SWI {0} is handled by jimulator, so it can't have a handler")]
+    SwiHandledByJimulator(u32),
+
+    #[error("An interrupt timer's period must be at least 1 step")]
+    InvalidTimerPeriod,
+
+    #[error("Too many watchpoints are defined")]
+    TooManyWatchpoints,
+
+    #[error("There is already a peripheral called {0}")]
+    PeripheralAlreadyExists(String),
+
+    #[error("There is no peripheral called {0}")]
+    NoSuchPeripheral(String),
+
+    #[error("The peripheral would overlap {0}")]
+    PeripheralOverlaps(String),
+
+    #[error("{0:#08x} is not a word aligned address in jimulator's memory")]
+    InvalidPeripheralAddress(u32),
+
+    #[error("{0} is not the right kind of peripheral for that")]
+    WrongPeripheralKind(String),
+
+    #[error("{0}")]
+    DecoderError(#[from] DecoderError),
 }
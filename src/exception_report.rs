@@ -0,0 +1,110 @@
+use crate::{decoded_instruction::DecodedInstruction, interrupt::CPSR_MODE_MASK};
+
+/// The exceptions with a vector in the table at 0x04-0x1C.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum ExceptionKind {
+    Undefined,
+    Swi,
+    PrefetchAbort,
+    DataAbort,
+    Irq,
+    Fiq,
+}
+
+impl ExceptionKind {
+    /// The exception whose vector is at `address`, if there is one
+    pub fn at_vector(address: u32) -> Option<Self> {
+        match address {
+            0x04 => Some(Self::Undefined),
+            0x08 => Some(Self::Swi),
+            0x0C => Some(Self::PrefetchAbort),
+            0x10 => Some(Self::DataAbort),
+            0x18 => Some(Self::Irq),
+            0x1C => Some(Self::Fiq),
+            _ => None,
+        }
+    }
+
+    /// The mode the processor is switched to on entry
+    pub fn mode(self) -> ProcessorMode {
+        match self {
+            Self::Undefined => ProcessorMode::Undefined,
+            Self::Swi => ProcessorMode::Supervisor,
+            Self::PrefetchAbort | Self::DataAbort => ProcessorMode::Abort,
+            Self::Irq => ProcessorMode::Irq,
+            Self::Fiq => ProcessorMode::Fiq,
+        }
+    }
+
+    /// How far the LR is set past the instruction that caused the exception (or, for interrupts,
+    /// the one that was interrupted). jimulator sets it to the next instruction for undefined
+    /// instructions, SWIs and `BKPT`s, even in Thumb state.
+    pub fn return_offset(self, was_thumb: bool) -> u32 {
+        match self {
+            Self::Undefined | Self::Swi | Self::PrefetchAbort if was_thumb => 2,
+            Self::Undefined | Self::Swi | Self::PrefetchAbort | Self::Irq | Self::Fiq => 4,
+            Self::DataAbort => 8,
+        }
+    }
+
+    /// What the exception is called, with an article, e.g. "an undefined instruction"
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Undefined => "an undefined instruction",
+            Self::Swi => "a SWI",
+            Self::PrefetchAbort => "a prefetch abort",
+            Self::DataAbort => "a data abort",
+            Self::Irq => "an IRQ",
+            Self::Fiq => "an FIQ",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum ProcessorMode {
+    User,
+    Fiq,
+    Irq,
+    Supervisor,
+    Abort,
+    Undefined,
+    System,
+}
+
+impl ProcessorMode {
+    /// The mode `cpsr` is in, or `None` if its mode bits aren't a valid mode
+    pub fn from_cpsr(cpsr: u32) -> Option<Self> {
+        match cpsr & CPSR_MODE_MASK {
+            0b1_0000 => Some(Self::User),
+            0b1_0001 => Some(Self::Fiq),
+            0b1_0010 => Some(Self::Irq),
+            0b1_0011 => Some(Self::Supervisor),
+            0b1_0111 => Some(Self::Abort),
+            0b1_1011 => Some(Self::Undefined),
+            0b1_1111 => Some(Self::System),
+            _ => None,
+        }
+    }
+}
+
+/// What the program was doing when it raised an exception.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct ExceptionReport {
+    pub kind: ExceptionKind,
+
+    /// The address of the instruction that caused the exception (or, for interrupts, the one that
+    /// was interrupted), worked out from the LR
+    pub address: u32,
+
+    /// The instruction at `address`, if it decodes
+    pub instruction: Option<DecodedInstruction>,
+
+    /// The source line that assembled to `address`, if it is part of the loaded KMD
+    pub source_line: Option<String>,
+
+    /// The mode the processor was in before the exception, from the SPSR
+    pub previous_mode: Option<ProcessorMode>,
+
+    /// The CPSR before the exception, i.e. the exception mode's SPSR
+    pub previous_cpsr: u32,
+}
@@ -0,0 +1,11 @@
+/// What a function called by [`crate::IguanaEnvironment::call_function`] returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct FunctionReturn {
+    pub r0: u32,
+
+    /// The top half of a 64-bit result
+    pub r1: u32,
+
+    /// The steps the function ran for, including its return
+    pub steps: u32,
+}
@@ -0,0 +1,11 @@
+/// A change in whether the program is waiting for input, as reported by
+/// [`crate::IguanaEnvironment::poll_input_event`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum InputEvent {
+    /// The program has stopped in `SWI 1` until a character is written to terminal 0
+    AwaitingInput,
+
+    /// The program was waiting for input and isn't any more, because it got a character or was
+    /// stopped
+    InputReceived,
+}
@@ -0,0 +1,361 @@
+use crate::{
+    condition_code::ConditionCode,
+    decoded_instruction::{
+        DecodedInstruction, DecodedOperand, MemoryOffset, Shift, ShiftAmount, ShiftKind,
+    },
+    disassembly_options::{DisassemblyOptions, DisassemblySyntax, ImmediateBase},
+};
+
+/// The program counter and stack pointer
+const PC: u8 = 15;
+const SP: u8 = 13;
+
+/// The suffixes that come after the condition in pre-UAL syntax, e.g. the `B` in `LDREQB`
+const LOAD_STORE_SUFFIXES: [&str; 7] = ["B", "T", "BT", "H", "SB", "SH", "D"];
+
+/// Writes out `instruction` as set out by `options`. Branch and `ADR` targets are written as
+/// absolute addresses.
+#[uniffi::export]
+pub fn format_instruction(instruction: DecodedInstruction, options: DisassemblyOptions) -> String {
+    format(&instruction, &options, &|_| None)
+}
+
+/// Writes out `instruction` as set out by `options`. `target_name` gives the label to write for a
+/// branch or `ADR` target, if there is one.
+pub fn format(
+    instruction: &DecodedInstruction,
+    options: &DisassemblyOptions,
+    target_name: &dyn Fn(u32) -> Option<String>,
+) -> String {
+    let formatter = Formatter {
+        options,
+        target_name,
+    };
+
+    formatter.format(instruction)
+}
+
+struct Formatter<'a> {
+    options: &'a DisassemblyOptions,
+    target_name: &'a dyn Fn(u32) -> Option<String>,
+}
+
+impl Formatter<'_> {
+    fn format(&self, instruction: &DecodedInstruction) -> String {
+        let is_komo = self.options.syntax == DisassemblySyntax::Komo;
+        let recover = self.options.recover_pseudo_ops;
+
+        let (base, suffix) = split_mnemonic(&instruction.mnemonic);
+
+        let mut base = base.to_string();
+        let mut suffix = suffix.to_string();
+
+        // 16-bit Thumb instructions nearly all set the flags, and pre-UAL syntax doesn't say so
+        let sets_flags = instruction.sets_flags
+            && !matches!(base.as_str(), "TST" | "TEQ" | "CMP" | "CMN")
+            && !(is_komo && instruction.length == 2);
+
+        let mut operands = instruction
+            .operands
+            .iter()
+            .map(|operand| self.operand(instruction, operand))
+            .collect::<Vec<_>>();
+
+        let stack_writeback = matches!(
+            instruction.operands.first(),
+            Some(DecodedOperand::RegisterWriteback {
+                register: SP,
+                writeback: true
+            })
+        );
+
+        use DecodedOperand::{Immediate, Register, RegisterList, ShiftedRegister};
+
+        match (base.as_str(), instruction.operands.as_slice()) {
+            // `MOV R0, R0` for ARM, `MOV R8, R8` for Thumb
+            ("MOV", [Register { register: first }, Register { register: second }])
+                if recover
+                    && first == second
+                    && *first == if instruction.length == 2 { 8 } else { 0 }
+                    && !instruction.sets_flags
+                    && instruction.condition == ConditionCode::Al =>
+            {
+                base = String::from("NOP");
+                operands.clear();
+            }
+
+            ("LDM" | "STM", [_, RegisterList { .. }])
+                if recover
+                    && stack_writeback
+                    && matches!(
+                        (base.as_str(), suffix.as_str()),
+                        ("LDM", "IA") | ("STM", "DB")
+                    ) =>
+            {
+                base = String::from(if base == "LDM" { "POP" } else { "PUSH" });
+                suffix.clear();
+                operands.remove(0);
+            }
+
+            ("ADR" | "ADD" | "SUB", [Register { .. }, ..])
+                if recover && !instruction.sets_flags && instruction.adr_target.is_some() =>
+            {
+                base = String::from("ADR");
+                operands.truncate(1);
+                operands.extend(instruction.adr_target.map(|target| self.target(target)));
+            }
+
+            // Thumb `ADR`, which yaxpeax writes without the PC
+            ("ADR", [Register { .. }, Immediate { .. }]) => {
+                base = String::from("ADD");
+                operands.insert(1, self.case(register_name(PC)));
+            }
+
+            ("ADR", _) => base = String::from("ADD"),
+
+            // UAL writes moves of shifted registers as shifts
+            ("MOV", [Register { .. }, ShiftedRegister { register, shift }]) if !is_komo => {
+                let source = self.case(register_name(*register));
+
+                match (shift.kind, shift.amount) {
+                    (ShiftKind::Lsl, ShiftAmount::Immediate { amount: 0 }) => {}
+                    (ShiftKind::Ror, ShiftAmount::Immediate { amount: 0 }) => {
+                        base = String::from("RRX");
+                        operands[1] = source;
+                    }
+                    (kind, amount) => {
+                        base = String::from(shift_name(kind));
+                        operands[1] = source;
+                        operands.push(self.case(match amount {
+                            ShiftAmount::Immediate { amount: 0 } => String::from("#32"),
+                            ShiftAmount::Immediate { amount } => format!("#{amount}"),
+                            ShiftAmount::Register { register } => register_name(register),
+                        }));
+                    }
+                }
+            }
+
+            ("SVC", [Immediate { value }]) if is_komo => {
+                base = String::from("SWI");
+                operands[0] = self.case(self.number(*value));
+            }
+
+            ("LDM" | "STM", _) if is_komo && stack_writeback => {
+                suffix = String::from(stack_mode(&base, &suffix));
+            }
+
+            ("LDM" | "STM", _) if !is_komo && suffix == "IA" => suffix.clear(),
+
+            _ => {}
+        }
+
+        let condition = condition_suffix(instruction.condition);
+        let s = if sets_flags { "S" } else { "" };
+
+        let mnemonic = self.case(if is_komo {
+            format!("{base}{condition}{suffix}{s}")
+        } else {
+            format!("{base}{suffix}{s}{condition}")
+        });
+
+        if operands.is_empty() {
+            mnemonic
+        } else {
+            format!("{mnemonic} {}", operands.join(", "))
+        }
+    }
+
+    fn operand(&self, instruction: &DecodedInstruction, operand: &DecodedOperand) -> String {
+        match operand {
+            DecodedOperand::Register { register } => self.case(register_name(*register)),
+            DecodedOperand::RegisterWriteback {
+                register,
+                writeback,
+            } => {
+                let writeback = if *writeback { "!" } else { "" };
+
+                self.case(format!("{}{writeback}", register_name(*register)))
+            }
+            DecodedOperand::RegisterList { registers } => self.case(register_list(registers)),
+            DecodedOperand::ShiftedRegister { register, shift } => {
+                self.case(shifted_register(*register, shift))
+            }
+            DecodedOperand::Memory {
+                base,
+                offset,
+                subtract,
+                is_preindexed,
+                writeback,
+            } => {
+                let sign = if *subtract { "-" } else { "" };
+
+                let offset = match offset {
+                    MemoryOffset::None => None,
+                    MemoryOffset::Immediate { value: 0 } if !subtract => None,
+                    MemoryOffset::Immediate { value } => {
+                        Some(format!("#{sign}{}", self.number(*value)))
+                    }
+                    MemoryOffset::Register { register } => {
+                        Some(format!("{sign}{}", register_name(*register)))
+                    }
+                    MemoryOffset::ShiftedRegister { register, shift } => {
+                        Some(format!("{sign}{}", shifted_register(*register, shift)))
+                    }
+                };
+
+                let base = register_name(*base);
+                let writeback = if *writeback { "!" } else { "" };
+
+                self.case(match offset {
+                    None if *is_preindexed => format!("[{base}]{writeback}"),
+                    None => format!("[{base}]"),
+                    Some(offset) if *is_preindexed => format!("[{base}, {offset}]{writeback}"),
+                    Some(offset) => format!("[{base}], {offset}"),
+                })
+            }
+            DecodedOperand::Immediate { value } => self.case(format!("#{}", self.number(*value))),
+            DecodedOperand::BranchOffset { .. } => instruction
+                .branch_target
+                .map(|target| self.target(target))
+                .unwrap_or_default(),
+            DecodedOperand::Other { text } => self.case(text.clone()),
+        }
+    }
+
+    /// The label at `target`, or its address if there isn't one
+    fn target(&self, target: u32) -> String {
+        (self.target_name)(target).unwrap_or_else(|| self.case(format!("0x{target:08X}")))
+    }
+
+    fn number(&self, value: u32) -> String {
+        match self.options.immediate_base {
+            ImmediateBase::Hexadecimal => format!("0x{value:X}"),
+            ImmediateBase::Decimal => value.to_string(),
+        }
+    }
+
+    /// Everything is built in upper case, so this only has to handle lower case
+    fn case(&self, text: String) -> String {
+        if self.options.lower_case {
+            text.to_lowercase()
+        } else {
+            text
+        }
+    }
+}
+
+/// Splits a mnemonic into the part that comes before the condition and the part that comes after
+/// it in pre-UAL syntax, e.g. `LDR` and `SB` for `LDRSB`.
+fn split_mnemonic(mnemonic: &str) -> (&str, &str) {
+    let split = |at: usize| mnemonic.split_at(at);
+
+    match mnemonic {
+        "SWPB" => split(3),
+        _ if mnemonic.starts_with("LDM") || mnemonic.starts_with("STM") => split(3),
+        _ if (mnemonic.starts_with("LDR") || mnemonic.starts_with("STR"))
+            && LOAD_STORE_SUFFIXES.contains(&&mnemonic[3..]) =>
+        {
+            split(3)
+        }
+        _ => (mnemonic, ""),
+    }
+}
+
+/// The pre-UAL stack addressing mode for an `LDM`/`STM` mode, e.g. `FD` for `LDMIA`
+fn stack_mode<'a>(base: &str, mode: &'a str) -> &'a str {
+    match (base, mode) {
+        ("LDM", "IA") | ("STM", "DB") => "FD",
+        ("LDM", "IB") | ("STM", "DA") => "ED",
+        ("LDM", "DA") | ("STM", "IB") => "FA",
+        ("LDM", "DB") | ("STM", "IA") => "EA",
+        _ => mode,
+    }
+}
+
+fn condition_suffix(condition: ConditionCode) -> &'static str {
+    match condition {
+        ConditionCode::Eq => "EQ",
+        ConditionCode::Ne => "NE",
+        ConditionCode::Cs => "CS",
+        ConditionCode::Cc => "CC",
+        ConditionCode::Mi => "MI",
+        ConditionCode::Pl => "PL",
+        ConditionCode::Vs => "VS",
+        ConditionCode::Vc => "VC",
+        ConditionCode::Hi => "HI",
+        ConditionCode::Ls => "LS",
+        ConditionCode::Ge => "GE",
+        ConditionCode::Lt => "LT",
+        ConditionCode::Gt => "GT",
+        ConditionCode::Le => "LE",
+        ConditionCode::Al => "",
+    }
+}
+
+pub fn register_name(register: u8) -> String {
+    match register {
+        13 => String::from("SP"),
+        14 => String::from("LR"),
+        15 => String::from("PC"),
+        _ => format!("R{register}"),
+    }
+}
+
+/// A register list like `{R0-R3, LR}`. Only runs of three or more of R0-R12 are written as ranges.
+fn register_list(registers: &[u8]) -> String {
+    let mut parts = Vec::new();
+    let mut index = 0;
+
+    while index < registers.len() {
+        let mut end = index;
+
+        while end + 1 < registers.len()
+            && registers[end + 1] == registers[end] + 1
+            && registers[end + 1] <= 12
+        {
+            end += 1;
+        }
+
+        if end - index >= 2 {
+            parts.push(format!(
+                "{}-{}",
+                register_name(registers[index]),
+                register_name(registers[end])
+            ));
+
+            index = end + 1;
+        } else {
+            parts.push(register_name(registers[index]));
+
+            index += 1;
+        }
+    }
+
+    format!("{{{}}}", parts.join(", "))
+}
+
+fn shift_name(kind: ShiftKind) -> &'static str {
+    match kind {
+        ShiftKind::Lsl => "LSL",
+        ShiftKind::Lsr => "LSR",
+        ShiftKind::Asr => "ASR",
+        ShiftKind::Ror => "ROR",
+    }
+}
+
+/// A register and its shift, e.g. `R1, LSL #2`. Immediate shifts of 0 are written the way the
+/// encoding means them: nothing for `LSL`, 32 for `LSR` and `ASR`, and `RRX` for `ROR`.
+fn shifted_register(register: u8, shift: &Shift) -> String {
+    let register = register_name(register);
+    let kind = shift_name(shift.kind);
+
+    match (shift.kind, shift.amount) {
+        (ShiftKind::Lsl, ShiftAmount::Immediate { amount: 0 }) => register,
+        (ShiftKind::Ror, ShiftAmount::Immediate { amount: 0 }) => format!("{register}, RRX"),
+        (_, ShiftAmount::Immediate { amount: 0 }) => format!("{register}, {kind} #32"),
+        (_, ShiftAmount::Immediate { amount }) => format!("{register}, {kind} #{amount}"),
+        (_, ShiftAmount::Register { register: shifter }) => {
+            format!("{register}, {kind} {}", register_name(shifter))
+        }
+    }
+}
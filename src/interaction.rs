@@ -0,0 +1,249 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    run_outcome::RunOutcome, status::Status, terminal_stream::TerminalStream, IguanaEnvironment,
+    LibiguanaError,
+};
+
+/// The terminal jimulator's SWIs read from and write to
+const SWI_TERMINAL: u8 = 0;
+
+/// How long to let the program run between looks at its output
+const RUN_SLICE_MS: u32 = 10;
+
+/// One round of a conversation with the program: what it wrote, and what it was sent in reply.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct Exchange {
+    pub output: String,
+
+    /// `None` for the last exchange if the program hasn't been sent anything since its last output
+    pub input: Option<String>,
+
+    /// The steps the program ran between the previous exchange and this one
+    pub steps: u32,
+
+    pub steps_since_reset: u32,
+}
+
+struct InteractionState {
+    /// Output that no `expect` has matched yet
+    unmatched_output: String,
+
+    /// Output since the last input, for the transcript
+    exchange_output: String,
+
+    /// Input to send, in order, each time the program waits for input
+    queued_input: VecDeque<String>,
+
+    transcript: Vec<Exchange>,
+
+    /// The step count when the last exchange finished
+    steps_since_reset: u32,
+}
+
+/// A scripted conversation with the program over terminal 0, in the style of `expect`. The program
+/// is run from wherever it is when output is expected, and queued input is sent whenever it waits
+/// for some.
+#[derive(uniffi::Object)]
+pub struct Interaction {
+    environment: Arc<IguanaEnvironment>,
+    terminal: Arc<TerminalStream>,
+    state: Mutex<InteractionState>,
+}
+
+#[uniffi::export]
+impl Interaction {
+    #[uniffi::constructor]
+    pub fn new(environment: Arc<IguanaEnvironment>) -> Result<Self, LibiguanaError> {
+        let terminal = environment.terminal_stream(SWI_TERMINAL, false)?;
+        let steps_since_reset = environment.status()?.steps_since_reset;
+
+        Ok(Self {
+            environment,
+            terminal,
+            state: Mutex::new(InteractionState {
+                unmatched_output: String::new(),
+                exchange_output: String::new(),
+                queued_input: VecDeque::new(),
+                transcript: Vec::new(),
+                steps_since_reset,
+            }),
+        })
+    }
+
+    /// Adds `text` to the input that is sent, a piece at a time, whenever the program waits for
+    /// input.
+    pub fn queue_input(&self, text: String) {
+        self.state.lock().unwrap().queued_input.push_back(text);
+    }
+
+    /// Queues `line` followed by a newline.
+    pub fn queue_line(&self, line: String) {
+        self.queue_input(format!("{line}\n"));
+    }
+
+    /// Sends `text` to the program straight away.
+    pub fn send(&self, text: String) -> Result<(), LibiguanaError> {
+        let mut state = self.state.lock().unwrap();
+
+        self.read_output(&mut state)?;
+        self.send_input(&mut state, text)
+    }
+
+    /// Sends `line` followed by a newline.
+    pub fn send_line(&self, line: String) -> Result<(), LibiguanaError> {
+        self.send(format!("{line}\n"))
+    }
+
+    /// Runs the program until it writes `expected`, and returns everything it wrote up to and
+    /// including `expected`. Fails if the program stops, waits for input with none queued, or
+    /// runs for `timeout_ms` without writing it.
+    pub fn expect(&self, expected: String, timeout_ms: u32) -> Result<String, LibiguanaError> {
+        let mut state = self.state.lock().unwrap();
+
+        let found = |output: &str| output.find(&expected).map(|start| start + expected.len());
+
+        match self.run_until(&mut state, timeout_ms, found)? {
+            Ok(end) => Ok(state.unmatched_output.drain(..end).collect()),
+            Err(outcome) => Err(LibiguanaError::ExpectFailed {
+                expected,
+                reason: outcome.description(),
+                output: std::mem::take(&mut state.unmatched_output),
+            }),
+        }
+    }
+
+    /// Runs the program until it halts with `SWI 2`, and returns everything it wrote that hasn't
+    /// been matched by [`Self::expect`].
+    pub fn expect_finished(&self, timeout_ms: u32) -> Result<String, LibiguanaError> {
+        let mut state = self.state.lock().unwrap();
+
+        let outcome = match self.run_until(&mut state, timeout_ms, |_| None)? {
+            Ok(_) => unreachable!("nothing is being looked for in the output"),
+            Err(outcome) => outcome,
+        };
+
+        let output = std::mem::take(&mut state.unmatched_output);
+
+        match outcome {
+            RunOutcome::Stopped { state } if state.status == Status::Finished => Ok(output),
+            _ => Err(LibiguanaError::ProgramDidNotFinish {
+                reason: outcome.description(),
+                output,
+            }),
+        }
+    }
+
+    /// What the program has written that hasn't been matched by [`Self::expect`], without running
+    /// it any further.
+    pub fn unmatched_output(&self) -> Result<String, LibiguanaError> {
+        let mut state = self.state.lock().unwrap();
+
+        self.read_output(&mut state)?;
+
+        Ok(state.unmatched_output.clone())
+    }
+
+    /// Every exchange so far, ending with the output since the last input if there is any.
+    pub fn transcript(&self) -> Result<Vec<Exchange>, LibiguanaError> {
+        let mut state = self.state.lock().unwrap();
+
+        self.read_output(&mut state)?;
+
+        let mut transcript = state.transcript.clone();
+
+        if !state.exchange_output.is_empty() {
+            let steps_since_reset = self.environment.status()?.steps_since_reset;
+
+            transcript.push(Exchange {
+                output: state.exchange_output.clone(),
+                input: None,
+                steps: steps_since_reset.wrapping_sub(state.steps_since_reset),
+                steps_since_reset,
+            });
+        }
+
+        Ok(transcript)
+    }
+}
+
+impl Interaction {
+    /// Runs the program until `found` gives the end of what it's looking for in the unmatched
+    /// output, sending queued input as the program asks for it. Returns why the program couldn't
+    /// get any further if `found` never matches.
+    fn run_until(
+        &self,
+        state: &mut InteractionState,
+        timeout_ms: u32,
+        found: impl Fn(&str) -> Option<usize>,
+    ) -> Result<Result<usize, RunOutcome>, LibiguanaError> {
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.into());
+
+        // A program that has finished or faulted is left alone, since carrying on would run
+        // whatever comes after it
+        if matches!(
+            self.environment.status()?.status,
+            Status::Normal | Status::Stopped | Status::Breakpoint | Status::Watchpoint
+        ) {
+            self.environment.start_execution(0)?;
+        }
+
+        loop {
+            let outcome = self.environment.wait_until_stopped(RUN_SLICE_MS)?;
+
+            // The output is read after the program stops so that nothing it wrote is missed
+            self.read_output(state)?;
+
+            if let Some(end) = found(&state.unmatched_output) {
+                return Ok(Ok(end));
+            }
+
+            match outcome {
+                RunOutcome::AwaitingInput => match state.queued_input.pop_front() {
+                    Some(input) => self.send_input(state, input)?,
+                    None => return Ok(Err(outcome)),
+                },
+                RunOutcome::Stopped { .. } | RunOutcome::Exception { .. } => {
+                    return Ok(Err(outcome))
+                }
+                RunOutcome::TimedOut if Instant::now() >= deadline => return Ok(Err(outcome)),
+                RunOutcome::TimedOut => {}
+            }
+        }
+    }
+
+    fn read_output(&self, state: &mut InteractionState) -> Result<(), LibiguanaError> {
+        let output = self.terminal.read_text()?;
+
+        state.unmatched_output.push_str(&output);
+        state.exchange_output.push_str(&output);
+
+        Ok(())
+    }
+
+    /// Writes `input` to the terminal, ending the current exchange.
+    fn send_input(
+        &self,
+        state: &mut InteractionState,
+        input: String,
+    ) -> Result<(), LibiguanaError> {
+        self.terminal.write_text(&input)?;
+
+        let steps_since_reset = self.environment.status()?.steps_since_reset;
+
+        state.transcript.push(Exchange {
+            output: std::mem::take(&mut state.exchange_output),
+            input: Some(input),
+            steps: steps_since_reset.wrapping_sub(state.steps_since_reset),
+            steps_since_reset,
+        });
+
+        state.steps_since_reset = steps_since_reset;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+/// The CPSR's mode bits
+pub const CPSR_MODE_MASK: u32 = 0b1_1111;
+
+/// The CPSR bits that disable IRQs and FIQs
+const CPSR_IRQ_DISABLE: u32 = 0b1000_0000;
+const CPSR_FIQ_DISABLE: u32 = 0b0100_0000;
+
+/// An external interrupt, which jimulator has no way of raising itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum Interrupt {
+    Irq,
+    Fiq,
+}
+
+impl Interrupt {
+    /// The address the processor jumps to when it takes the interrupt
+    pub fn vector(self) -> u32 {
+        match self {
+            Self::Irq => 0x18,
+            Self::Fiq => 0x1C,
+        }
+    }
+
+    /// The processor mode the interrupt is handled in
+    pub fn mode(self) -> u32 {
+        match self {
+            Self::Irq => 0b1_0010,
+            Self::Fiq => 0b1_0001,
+        }
+    }
+
+    /// The CPSR bit that stops the interrupt from being taken
+    pub fn disable_bit(self) -> u32 {
+        match self {
+            Self::Irq => CPSR_IRQ_DISABLE,
+            Self::Fiq => CPSR_FIQ_DISABLE,
+        }
+    }
+
+    /// The CPSR bits that are set on entry, which disable the interrupt and anything it mustn't
+    /// be interrupted by
+    pub fn entry_disable_bits(self) -> u32 {
+        match self {
+            Self::Irq => CPSR_IRQ_DISABLE,
+            Self::Fiq => CPSR_IRQ_DISABLE | CPSR_FIQ_DISABLE,
+        }
+    }
+}
+
+/// Raises an interrupt every `period` steps of the program.
+pub struct InterruptTimer {
+    pub interrupt: Interrupt,
+    pub period: u32,
+
+    /// The step count (since reset) of the next tick
+    pub next_tick: u32,
+}
+
+impl InterruptTimer {
+    pub fn new(interrupt: Interrupt, period: u32, steps_since_reset: u32) -> Self {
+        Self {
+            interrupt,
+            period,
+            next_tick: steps_since_reset.wrapping_add(period),
+        }
+    }
+
+    /// How many steps jimulator should run for, starting at `steps_since_reset`, before stopping
+    /// for the next tick. A tick that can't be reached from here (because the board has been
+    /// reset, say) is moved to a period from now.
+    pub fn steps_until_tick(&mut self, steps_since_reset: u32) -> u32 {
+        let mut steps_until_tick = self.next_tick.wrapping_sub(steps_since_reset);
+
+        if steps_until_tick == 0 || steps_until_tick > self.period {
+            self.next_tick = steps_since_reset.wrapping_add(self.period);
+            steps_until_tick = self.period;
+        }
+
+        steps_until_tick
+    }
+}
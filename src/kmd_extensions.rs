@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+
+use crate::kmdparse_types::{
+    label::KmdparseLabel, line::KmdparseLine, token::KmdparseToken, word::KmdparseWord,
+};
+
+pub trait KmdExtensions {
+    fn image(&self) -> BTreeMap<u32, u8>;
+    fn labels(&self) -> Vec<&KmdparseLabel>;
+    fn label_named(&self, name: &str) -> Option<&KmdparseLabel>;
+    fn covering_label(&self, address: u32) -> Option<&KmdparseLabel>;
+    fn covering_line(&self, address: u32) -> Option<&KmdparseLine>;
+    fn data_ranges(&self) -> BTreeMap<u32, u32>;
+}
+
+impl KmdExtensions for [KmdparseToken] {
+    /// Flattens every line with a word into a map of [memory address : byte], which is what memory
+    /// should look like straight after the file is loaded.
+    fn image(&self) -> BTreeMap<u32, u8> {
+        let mut image = BTreeMap::new();
+
+        for line in self.iter().filter_map(as_line) {
+            if let (Some(word), Some(memory_address)) = (&line.word, line.memory_address) {
+                for (offset, byte) in word.bytes().iter().enumerate() {
+                    image.insert(memory_address.wrapping_add(offset as u32), *byte);
+                }
+            }
+        }
+
+        image
+    }
+
+    fn labels(&self) -> Vec<&KmdparseLabel> {
+        self.iter()
+            .filter_map(|token| match token {
+                KmdparseToken::Label { label } => Some(label),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn label_named(&self, name: &str) -> Option<&KmdparseLabel> {
+        self.labels().into_iter().find(|label| label.name == name)
+    }
+
+    /// Returns the label with the highest address that is still at or below `address`, i.e. the
+    /// label that `address` is most likely part of.
+    fn covering_label(&self, address: u32) -> Option<&KmdparseLabel> {
+        self.labels()
+            .into_iter()
+            .filter(|label| label.memory_address <= address)
+            .max_by_key(|label| label.memory_address)
+    }
+
+    /// Returns the line whose word `address` is part of, i.e. the line that put it there. Thumb
+    /// instructions still get 4-byte words, which overlap the next instruction, so the latest
+    /// line covering `address` is the one returned.
+    fn covering_line(&self, address: u32) -> Option<&KmdparseLine> {
+        self.iter()
+            .filter_map(as_line)
+            .filter(|line| match (&line.word, line.memory_address) {
+                (Some(word), Some(memory_address)) => {
+                    address.wrapping_sub(memory_address) < word.bytes().len() as u32
+                }
+                _ => false,
+            })
+            .max_by_key(|line| line.memory_address)
+    }
+
+    /// Returns every line holding data (rather than an instruction), as a map of
+    /// [memory address : length in bytes].
+    fn data_ranges(&self) -> BTreeMap<u32, u32> {
+        self.iter()
+            .filter_map(as_line)
+            .filter_map(|line| match (&line.word, line.memory_address) {
+                (Some(KmdparseWord::Data { data }), Some(memory_address)) => {
+                    Some((memory_address, data.len() as u32))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn as_line(token: &KmdparseToken) -> Option<&KmdparseLine> {
+    match token {
+        KmdparseToken::Line { line } => Some(line),
+        _ => None,
+    }
+}
@@ -9,6 +9,17 @@ pub struct KmdparseLine {
     pub comment: String,
 }
 
+impl KmdparseLine {
+    /// Replaces the source text in the line's comment with `source`, keeping its label (if it
+    /// has one).
+    pub fn set_source(&mut self, source: &str) {
+        let text = self.comment.strip_prefix(' ').unwrap_or(&self.comment);
+        let label = &text[..text.find(char::is_whitespace).unwrap_or(text.len())];
+
+        self.comment = format!(" {label}\t{}", source.trim());
+    }
+}
+
 impl From<Line> for KmdparseLine {
     fn from(value: Line) -> Self {
         Self {
@@ -12,6 +12,16 @@ pub enum KmdparseWord {
     },
 }
 
+impl KmdparseWord {
+    /// The bytes of the word, in the order they are laid out in memory.
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Self::Instruction { instruction } => instruction,
+            Self::Data { data } => data,
+        }
+    }
+}
+
 impl From<Word> for KmdparseWord {
     fn from(value: Word) -> Self {
         match value {
@@ -1,34 +1,158 @@
 use std::{
     array::TryFromSliceError,
-    collections::HashMap,
-    path::Path,
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    path::{Path, PathBuf},
     process::{Child, Command, Stdio},
     str,
     sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
 };
 
+mod aasm_diagnostic;
 mod aasm_output;
+mod aasm_source;
+mod aasm_symbol;
 pub mod arm_decoder;
+mod compatibility;
+mod compile_options;
+mod compile_result;
+mod condition_code;
+mod control_flow_graph;
+mod decoded_instruction;
+mod disassembly;
+mod disassembly_options;
 mod error;
+mod exception_report;
+mod function_return;
+mod input_event;
+mod instruction_formatter;
+mod interaction;
+mod interrupt;
+mod kmd_extensions;
 mod kmdparse_types;
+mod memory_mismatch;
+mod mnemonic_catalogue;
+mod peripheral;
 mod reader_writer;
 mod registers;
+mod reload_report;
+mod run_outcome;
 mod status;
+mod swi_handler;
+mod temp_dir;
+mod terminal;
+mod terminal_stream;
+pub mod testing;
 mod uniffi_array;
 
+use interrupt::{InterruptTimer, CPSR_MODE_MASK};
+use kmd_extensions::KmdExtensions;
 use kmdparse::{parse_kmd, token::Token, word::Word};
-use kmdparse_types::token::KmdparseToken;
+use kmdparse_types::{token::KmdparseToken, word::KmdparseWord};
+use peripheral::{Peripheral, TIMER_PERIOD_OFFSET};
 use reader_writer::ReaderWriter;
+use swi_handler::{SwiHandlers, SWI_VECTOR};
+use temp_dir::TempDir;
+use terminal::TERMINAL_COUNT;
 
 use crate::status::BoardState;
 
+pub use self::aasm_diagnostic::{AasmDiagnostic, AasmDiagnosticSeverity, AasmErrorKind};
 pub use self::aasm_output::AasmOutput;
+pub use self::aasm_symbol::{
+    AasmLiteral, AasmLocalLabel, AasmSymbol, AasmSymbolKind, AasmSymbolTable,
+};
+pub use self::compatibility::{CompatibilityIssue, CompatibilityIssueKind};
+pub use self::compile_options::{CompileOptions, SymbolOrder, SymbolTableOptions};
+pub use self::compile_result::CompileResult;
+pub use self::condition_code::ConditionCode;
+pub use self::control_flow_graph::{
+    BasicBlock, BlockExit, ControlFlowEdge, ControlFlowGraph, EdgeKind,
+};
+pub use self::decoded_instruction::{
+    DecodedInstruction, DecodedOperand, MemoryAccess, MemoryAccessKind, MemoryOffset, Shift,
+    ShiftAmount, ShiftKind,
+};
+pub use self::disassembly::DisassemblyLine;
+pub use self::disassembly_options::{DisassemblyOptions, DisassemblySyntax, ImmediateBase};
 pub use self::error::LibiguanaError;
+pub use self::exception_report::{ExceptionKind, ExceptionReport, ProcessorMode};
+pub use self::function_return::FunctionReturn;
+pub use self::input_event::InputEvent;
+pub use self::instruction_formatter::format_instruction;
+pub use self::interaction::{Exchange, Interaction};
+pub use self::interrupt::Interrupt;
+pub use self::memory_mismatch::MemoryMismatch;
+pub use self::mnemonic_catalogue::{DirectiveKind, Mnemonic, MnemonicCatalogue, MnemonicSet};
+pub use self::peripheral::{
+    PeripheralAccess, PeripheralAccessKind, PeripheralKind, PeripheralObserver,
+};
 pub use self::registers::Registers;
+pub use self::reload_report::{MovedBreakpoint, MovedSymbol, ReloadReport};
+pub use self::run_outcome::RunOutcome;
 pub use self::status::Status;
+pub use self::swi_handler::SwiHandler;
+pub use self::terminal::Terminal;
+pub use self::terminal_stream::TerminalStream;
 
 uniffi::setup_scaffolding!();
 
+/// The number of breakpoints jimulator has room for (`NO_OF_BREAKPOINTS` in jimulator.cpp)
+const MAX_BREAKPOINTS: usize = 32;
+
+/// The number of watchpoints jimulator has room for (`NO_OF_WATCHPOINTS` in jimulator.cpp)
+const MAX_WATCHPOINTS: u8 = 4;
+
+/// The size of jimulator's memory. Addresses above this wrap around.
+const MEMORY_SIZE: u32 = 0x10_0000;
+
+/// The register space addresses of the SP, CPSR and SPSR in the current mode's bank
+const SP_REGISTER: u32 = 13;
+const CPSR_REGISTER: u32 = 16;
+const SPSR_REGISTER: u32 = 17;
+
+/// The LR and PC, as `write_register` numbers them
+const LR_REGISTER: u8 = 14;
+const PC_REGISTER: u8 = 15;
+
+/// The exception vectors take up the bottom 32 bytes of memory, so a trap on these address bits
+/// being zero stops on all of them
+const EXCEPTION_VECTORS_MASK: u32 = !0b1_1111;
+
+/// The SP and CPSR jimulator starts with - supervisor mode with interrupts disabled, and an SP of 0
+const INITIAL_SP: u32 = 0;
+const INITIAL_CPSR: u32 = 0b1101_0011;
+
+/// The T bit of the CPSR, which is set when the processor is executing Thumb code
+const CPSR_THUMB_BIT: u32 = 0b0010_0000;
+
+/// `SWI 1`, which waits for a character from terminal 0, without the condition code for ARM
+const ARM_INPUT_SWI: u32 = 0x0F00_0001;
+const THUMB_INPUT_SWI: u16 = 0xDF01;
+
+/// The top 5 bits of the first half of a Thumb `BL`, which is the only 32-bit Thumb instruction
+const THUMB_BL_PREFIX_MASK: u16 = 0xF800;
+const THUMB_BL_PREFIX: u16 = 0xF000;
+
+/// The return address `call_function` gives the function, which has a breakpoint put on it to
+/// catch the return. Nothing is ever loaded this high up.
+const RETURN_SENTINEL: u32 = 0xFFFF_FFFC;
+
+/// The registers that hold the first four arguments under the ARM procedure call standard
+const ARGUMENT_REGISTERS: usize = 4;
+
+/// The highest SWI number jimulator handles itself
+const LAST_JIMULATOR_SWI: u32 = 4;
+
+/// How far past the next instruction the LR points on entry to an interrupt handler, which
+/// returns with `SUBS PC, LR, #4`
+const INTERRUPT_RETURN_OFFSET: u32 = 4;
+
+/// How long to wait between looks at a running program
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
 #[derive(uniffi::Object)]
 pub struct IguanaEnvironment {
     /// The jimulator process that `IguanaEnvironment` controls. This process is killed on `Drop`.
@@ -48,6 +172,40 @@ pub struct IguanaEnvironment {
 
     /// The used trap numbers, with `true` meaning used and `false` meaning unused.
     used_trap_numbers: Arc<Mutex<[bool; u8::MAX as usize]>>,
+
+    /// A handle for each of jimulator's terminals, indexed by device ID
+    terminals: [Arc<Terminal>; TERMINAL_COUNT],
+
+    /// Whether the program was waiting for input when `poll_input_event` last looked
+    was_awaiting_input: Mutex<bool>,
+
+    /// The host-side handlers for SWIs that jimulator doesn't handle, by SWI number
+    swi_handlers: Mutex<SwiHandlers>,
+
+    /// The timer raising periodic interrupts, if there is one
+    interrupt_timer: Mutex<Option<InterruptTimer>>,
+
+    /// The timer a `Timer` peripheral's period register sets, kept apart from the one above so
+    /// that the program and the host can't replace each other's
+    peripheral_timer: Mutex<Option<InterruptTimer>>,
+
+    /// The step count the current run was asked to stop at, or `None` if it was asked to run
+    /// indefinitely. jimulator is sometimes stopped before then (for the interrupt timer, say),
+    /// so this is what lets the run carry on afterwards.
+    run_end: Mutex<Option<u32>>,
+
+    /// The virtual peripherals mapped into memory
+    peripherals: Mutex<Vec<Peripheral>>,
+
+    /// The trap on the exception vectors, while exceptions are being reported
+    exception_trap: Mutex<Option<u8>>,
+
+    /// The last exception [`Self::wait_until_stopped`] stopped for, and the step count the program
+    /// was last stopped on its vector at. A handler that loops back to its own vector (`B .`, say)
+    /// looks just like the exception being raised again, so this is what lets the program carry
+    /// on into the handler once it has been reported. It is forgotten once the program leaves the
+    /// vector.
+    reported_exception: Mutex<Option<(ExceptionReport, u32)>>,
 }
 
 #[uniffi::export]
@@ -81,6 +239,9 @@ impl IguanaEnvironment {
 
         let traps = Arc::new(Mutex::new(HashMap::new()));
 
+        let terminals =
+            [0, 1].map(|device| Arc::new(Terminal::new(device, jimulator_arc_mutex.clone())));
+
         Ok(Self {
             jimulator_process: jimulator_arc_mutex,
             current_kmd: Arc::new(Mutex::new(None)),
@@ -88,32 +249,211 @@ impl IguanaEnvironment {
             mnemonics_path,
             traps,
             used_trap_numbers: Arc::new(Mutex::new([false; u8::MAX as usize])),
+            terminals,
+            was_awaiting_input: Mutex::new(false),
+            swi_handlers: Mutex::new(SwiHandlers::default()),
+            interrupt_timer: Mutex::new(None),
+            peripheral_timer: Mutex::new(None),
+            run_end: Mutex::new(None),
+            peripherals: Mutex::new(Vec::new()),
+            exception_trap: Mutex::new(None),
+            reported_exception: Mutex::new(None),
         })
     }
 
-    pub fn compile_aasm(&self, aasm_path: &str) -> Result<AasmOutput, LibiguanaError> {
+    /// Assembles `source` and, if aasm succeeded, loads the result. Emulator memory is left alone
+    /// if assembly failed.
+    pub fn assemble_and_load(&self, source: &str) -> Result<CompileResult, LibiguanaError> {
+        let compile_result = self.compile_aasm_source(source, "source.s", None)?;
+
+        if compile_result.is_success() {
+            self.load_kmd(&compile_result.output().kmd)?;
+        }
+
+        Ok(compile_result)
+    }
+
+    /// Assembles the file at `aasm_path`. Check the result before loading the KMD - if aasm fails,
+    /// the KMD it outputs will be empty or incomplete.
+    pub fn compile_aasm(&self, aasm_path: &str) -> Result<CompileResult, LibiguanaError> {
+        self.compile_aasm_with_options(aasm_path, CompileOptions::default())
+    }
+
+    /// Assembles `source` directly, without the caller having to save it anywhere first. aasm's
+    /// messages refer to the file as `virtual_name`, and relative `INCLUDE`s are resolved against
+    /// `base_directory` (or the current directory if it is `None`).
+    pub fn compile_aasm_source(
+        &self,
+        source: &str,
+        virtual_name: &str,
+        base_directory: Option<String>,
+    ) -> Result<CompileResult, LibiguanaError> {
+        self.compile_aasm_source_with_options(
+            source,
+            virtual_name,
+            base_directory,
+            CompileOptions::default(),
+        )
+    }
+
+    /// Like [`Self::compile_aasm_source`], but also generates the outputs selected in `options`.
+    pub fn compile_aasm_source_with_options(
+        &self,
+        source: &str,
+        virtual_name: &str,
+        base_directory: Option<String>,
+        options: CompileOptions,
+    ) -> Result<CompileResult, LibiguanaError> {
+        let base_directory = match base_directory {
+            Some(base_directory) => PathBuf::from(base_directory),
+            None => env::current_dir()?,
+        };
+
+        // aasm rewinds its input on every pass, so the source has to be a real file rather than
+        // stdin. The temp dir (and the file in it) is deleted when it goes out of scope.
+        let temp_dir = TempDir::new()?;
+        let source_path = temp_dir.path().join("source.s");
+
+        fs::write(
+            &source_path,
+            aasm_source::resolve_includes(source, &base_directory),
+        )?;
+
+        let source_path = source_path.to_string_lossy();
+
+        let mut aasm_output = self
+            .compile_aasm_with_options(&source_path, options)?
+            .into_output();
+
+        // Point the terminal output and the diagnostics at the virtual name, not the temp file
+        aasm_output.aasm_terminal = aasm_output
+            .aasm_terminal
+            .replace(&*source_path, virtual_name);
+        aasm_output.diagnostics = AasmDiagnostic::parse_all(&aasm_output.aasm_terminal);
+
+        Ok(CompileResult::from(aasm_output))
+    }
+
+    /// Like [`Self::compile_aasm`], but also generates the outputs selected in `options`.
+    pub fn compile_aasm_with_options(
+        &self,
+        aasm_path: &str,
+        options: CompileOptions,
+    ) -> Result<CompileResult, LibiguanaError> {
+        // The KMD file goes to stderr, but everything else has to go to a file
+        let temp_dir = TempDir::new()?;
+
+        let elf_path = temp_dir.path().join("output.elf");
+        let hex_path = temp_dir.path().join("output.hex");
+        let symbols_path = temp_dir.path().join("output.sym");
+        let verilog_path = temp_dir.path().join("output.v");
+
+        let mut args = vec![
+            "-lk".to_string(),
+            "/dev/stderr".to_string(),
+            "-m".to_string(),
+            self.mnemonics_path.clone(),
+        ];
+
+        if options.elf {
+            args.push("-e".to_string());
+            args.push(elf_path.to_string_lossy().into_owned());
+        }
+
+        if options.hex {
+            args.push("-h".to_string());
+            args.push(hex_path.to_string_lossy().into_owned());
+        }
+
+        if let Some(symbol_table_options) = &options.symbol_table {
+            args.push(symbol_table_options.flag());
+            args.push(symbols_path.to_string_lossy().into_owned());
+        }
+
+        if options.verilog {
+            args.push("-v".to_string());
+            args.push(verilog_path.to_string_lossy().into_owned());
+
+            // aasm takes the size as hex in square brackets
+            if let Some(verilog_size) = options.verilog_size {
+                args.push(format!("[{verilog_size:X}]"));
+            }
+        }
+
+        args.push(aasm_path.to_string());
+
         let aasm_command = Command::new(&self.aasm_path)
-            .args(["-lk", "/dev/stderr", "-m", &self.mnemonics_path, aasm_path])
-            // .stdin(Stdio::piped())
+            .args(&args)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()?;
 
-        // Write the aasm string into aasm
-        // aasm_command
-        //     .stdin
-        //     .as_mut()
-        //     .ok_or(LibiguanaError::NoStdin)?
-        //     .write_all(aasm_string.as_bytes())?;
-
         let output = aasm_command.wait_with_output()?;
 
         let kmd = String::from_utf8(output.stderr)?;
         let aasm_terminal = String::from_utf8(output.stdout)?;
 
-        let aasm_output = AasmOutput { kmd, aasm_terminal };
+        let mut aasm_output = AasmOutput::new(kmd, aasm_terminal, output.status.code());
+
+        // aasm deletes its output files if assembly fails, so these are only read if they exist
+        if options.elf {
+            aasm_output.elf = fs::read(&elf_path).ok();
+        }
+
+        if options.hex {
+            aasm_output.hex = fs::read_to_string(&hex_path).ok();
+        }
+
+        if options.symbol_table.is_some() {
+            aasm_output.symbol_table = fs::read_to_string(&symbols_path)
+                .ok()
+                .map(|symbol_file| AasmSymbolTable::parse(&symbol_file));
+        }
+
+        if options.verilog {
+            aasm_output.verilog = fs::read_to_string(&verilog_path).ok();
+        }
+
+        Ok(CompileResult::from(aasm_output))
+    }
+
+    /// Calls the function at `label` and returns what it left in R0 and R1. See
+    /// [`Self::call_function_at`].
+    pub fn call_function(
+        &self,
+        label: &str,
+        arguments: Vec<u32>,
+        timeout_ms: u32,
+    ) -> Result<FunctionReturn, LibiguanaError> {
+        let (address, is_thumb) = self
+            .current_kmd
+            .lock()
+            .unwrap()
+            .as_deref()
+            .and_then(|kmd| kmd.label_named(label))
+            .map(|label| (label.memory_address, label.is_thumb))
+            .ok_or_else(|| LibiguanaError::NoSuchLabel(label.to_string()))?;
+
+        self.call(address, is_thumb, &arguments, timeout_ms)
+    }
 
-        Ok(aasm_output)
+    /// Calls the function at `address` the way the ARM procedure call standard says to: the first
+    /// four arguments go in R0-R3, the rest go on the stack, and the LR is a return address with a
+    /// breakpoint on it. The function gets the program's stack, unless the SP doesn't point into
+    /// jimulator's memory (it is 0 until the program sets it up, say), in which case it gets a
+    /// stack at the top of memory. Once the function returns (or fails to within `timeout_ms`),
+    /// every register, the CPSR and the memory the stacked arguments went in are put back as they
+    /// were. Anything else the function writes to memory, including below the stack pointer, is
+    /// left as it is.
+    pub fn call_function_at(
+        &self,
+        address: u32,
+        arguments: Vec<u32>,
+        timeout_ms: u32,
+    ) -> Result<FunctionReturn, LibiguanaError> {
+        let is_thumb = self.is_thumb_at(address)?;
+
+        self.call(address, is_thumb, &arguments, timeout_ms)
     }
 
     pub fn continue_execution(&self) -> Result<(), LibiguanaError> {
@@ -129,40 +469,132 @@ impl IguanaEnvironment {
         let mut traps = self.traps.lock().unwrap();
         let mut used_trap_numbers = self.used_trap_numbers.lock().unwrap();
 
-        let trap_number: u8 = used_trap_numbers
-            .iter()
-            .position(|is_used| !is_used)
-            .ok_or(LibiguanaError::TooManyTraps)? as u8;
-
-        // Initial define trap command
-        ReaderWriter::write(&[0b0011_0000], &mut process)?;
-
-        ReaderWriter::write(
-            &[
-                trap_number,
-                0b1111_1111, // Trap conditions
-                0b0000_1111, // Transfer size mask (all)
-            ],
-            &mut process,
-        )?;
-
-        // Trap address A and B
-        ReaderWriter::write(&memory_address.to_le_bytes(), &mut process)?;
-        ReaderWriter::write(&u32::MAX.to_le_bytes(), &mut process)?;
+        let trap_number = Self::allocate_trap_number(&mut used_trap_numbers)?;
 
-        // Data address A and B (unused by Iguana)
-        ReaderWriter::write(&[0; 16], &mut process)?;
+        Self::define_trap(trap_number, memory_address, &mut process)?;
 
         traps.insert(memory_address, trap_number);
-        used_trap_numbers[trap_number as usize] = true;
 
         Ok(())
     }
 
+    /// Returns the current program status register.
+    pub fn cpsr(&self) -> Result<u32, LibiguanaError> {
+        let mut process = self.jimulator_process.lock().unwrap();
+
+        Self::read_register_raw(CPSR_REGISTER, &mut process)
+    }
+
     pub fn current_kmd(&self) -> Option<Vec<KmdparseToken>> {
         self.current_kmd.lock().unwrap().clone()
     }
 
+    /// Decodes the instruction at `address` into its parts. ARM or Thumb is chosen the same way as
+    /// in [`Self::disassemble_at`].
+    pub fn decode_at(&self, address: u32) -> Result<DecodedInstruction, LibiguanaError> {
+        let bytes: [u8; 4] = self.read_memory_bytes(address, 4)?.as_slice().try_into()?;
+        let word = u32::from_le_bytes(bytes);
+
+        let instruction = if self.is_thumb_at(address)? {
+            arm_decoder::decode_instruction_details_thumb(word, address)?
+        } else {
+            arm_decoder::decode_instruction_details(word, address)?
+        };
+
+        Ok(instruction)
+    }
+
+    /// Disassembles the instruction at `address`. The instruction is decoded as Thumb if the label
+    /// covering `address` in the current KMD is a Thumb label, or, if there is no such label, if the
+    /// CPSR's T bit is set.
+    pub fn disassemble_at(&self, address: u32) -> Result<String, LibiguanaError> {
+        let bytes: [u8; 4] = self.read_memory_bytes(address, 4)?.as_slice().try_into()?;
+        let word = u32::from_le_bytes(bytes);
+
+        let instruction = if self.is_thumb_at(address)? {
+            arm_decoder::decode_instruction_thumb(word)?
+        } else {
+            arm_decoder::decode_instruction(word)?
+        };
+
+        Ok(instruction)
+    }
+
+    /// Disassembles memory from `start` up to (but not including) `end`, as it is now rather than
+    /// as it was loaded. Branch and `ADR` targets are replaced with labels from the current KMD, and
+    /// anything the KMD says is data is shown as `DEFB`/`DEFW`. Instructions are written in KoMo's
+    /// syntax - use [`Self::disassemble_range_with_options`] to change that. `end` is capped at
+    /// the end of jimulator's memory.
+    pub fn disassemble_range(
+        &self,
+        start: u32,
+        end: u32,
+    ) -> Result<Vec<DisassemblyLine>, LibiguanaError> {
+        self.disassemble_range_with_options(start, end, DisassemblyOptions::default())
+    }
+
+    /// Like [`Self::disassemble_range`], but with the syntax, case and so on set by `options`.
+    pub fn disassemble_range_with_options(
+        &self,
+        start: u32,
+        end: u32,
+        options: DisassemblyOptions,
+    ) -> Result<Vec<DisassemblyLine>, LibiguanaError> {
+        // Addresses past the end of jimulator's memory only alias the start of it
+        let end = end.min(MEMORY_SIZE);
+
+        if end <= start {
+            return Ok(Vec::new());
+        }
+
+        // The extra 2 bytes let a 32-bit Thumb instruction that starts 2 bytes before `end` be
+        // decoded
+        let length = end - start;
+        let memory = self.read_memory_bytes(start, length.checked_add(2).unwrap_or(length))?;
+
+        let kmd = self.current_kmd().unwrap_or_default();
+
+        let default_is_thumb = self.cpsr()? & CPSR_THUMB_BIT != 0;
+
+        Ok(disassembly::disassemble(
+            &memory,
+            start,
+            end,
+            &kmd,
+            default_is_thumb,
+            &options,
+        ))
+    }
+
+    /// Splits the program in the current KMD into basic blocks, joined by its branches, calls and
+    /// fall-throughs. The program is disassembled as it was loaded, and instructions without a
+    /// covering label are decoded as ARM. The graph is empty if nothing has been loaded.
+    pub fn control_flow_graph(&self) -> Arc<ControlFlowGraph> {
+        let kmd = self.current_kmd().unwrap_or_default();
+
+        Arc::new(ControlFlowGraph::new(&kmd, false))
+    }
+
+    /// Finds the instructions in the current KMD that jimulator can't run properly - ones it treats
+    /// as undefined (jumping to 0x04), runs as something else, skips, or sends to the SWI vector.
+    /// SWIs with a handler registered through [`Self::register_swi_handler`] aren't included.
+    /// Instructions without a covering label are checked as ARM. The report is empty if nothing
+    /// has been loaded.
+    pub fn compatibility_report(&self) -> Vec<CompatibilityIssue> {
+        let kmd = self.current_kmd().unwrap_or_default();
+
+        let host_swis = self
+            .swi_handlers
+            .lock()
+            .unwrap()
+            .handlers
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+
+        compatibility::compatibility_report(&kmd, false, &host_swis)
+    }
+
     /// Kills the underlying jimulator process. This function should not be used from within Rust -
     /// `IguanaEnvironment` implements `Drop` and handles killing the process for you. This exists
     /// because for some reason `Drop` isn't working through `uniffi`.
@@ -204,6 +636,14 @@ impl IguanaEnvironment {
         Ok(())
     }
 
+    /// Parses the mnemonics file aasm uses into a catalogue of every mnemonic and directive aasm
+    /// will accept.
+    pub fn mnemonic_catalogue(&self) -> Result<Arc<MnemonicCatalogue>, LibiguanaError> {
+        let mnemonics_file = fs::read_to_string(&self.mnemonics_path)?;
+
+        Ok(Arc::new(MnemonicCatalogue::new(&mnemonics_file)?))
+    }
+
     // Pauses execution.
     pub fn pause(&self) -> Result<(), LibiguanaError> {
         let mut process = self.jimulator_process.lock().unwrap();
@@ -213,6 +653,62 @@ impl IguanaEnvironment {
         Ok(())
     }
 
+    /// Assembles `source_line` (e.g. `MOV R0, #1`) as a single instruction at `address` and
+    /// writes it over whatever is there, updating the current KMD (including its source line) to
+    /// match. The instruction is assembled as Thumb if `address` is Thumb code, going by the same
+    /// rule as [`Self::disassemble_at`]. Returns the old word, which can be passed to
+    /// [`Self::write_word`] to undo the patch.
+    pub fn patch_instruction(
+        &self,
+        address: u32,
+        source_line: &str,
+    ) -> Result<u32, LibiguanaError> {
+        let is_thumb = self.is_thumb_at(address)?;
+        let mode = if is_thumb { "\tTHUMB\n" } else { "" };
+
+        let source = format!("\tORG {address:#x}\n{mode}\t{source_line}\n");
+
+        let compile_result = self.compile_aasm_source(&source, "patch.s", None)?;
+
+        if !compile_result.is_success() {
+            return Err(LibiguanaError::AssemblyFailed(
+                compile_result.into_output().aasm_terminal,
+            ));
+        }
+
+        let parsed = parse_kmd(&compile_result.output().kmd)
+            .map_err(|_| LibiguanaError::ParseError)?
+            .1;
+
+        // Anything else (e.g. a literal pool from `LDR R0, =...`) would need more than one word
+        let words = parsed
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Line(line) => line.memory_address.zip(line.word),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let instruction = match words.as_slice() {
+            [(memory_address, Word::Instruction(instruction))] if *memory_address == address => {
+                *instruction
+            }
+            _ => return Err(LibiguanaError::PatchNotSingleInstruction(address)),
+        };
+
+        // Thumb instructions are a halfword, apart from the prefix and suffix pair of a `BL`
+        let length = match is_thumb {
+            true if u16::from_le_bytes([instruction[0], instruction[1]]) & THUMB_BL_PREFIX_MASK
+                != THUMB_BL_PREFIX =>
+            {
+                2
+            }
+            _ => 4,
+        };
+
+        self.write_instruction(address, &instruction[..length], Some(source_line))
+    }
+
     pub fn ping(&self) -> Result<String, LibiguanaError> {
         let mut process = self.jimulator_process.lock().unwrap();
 
@@ -230,8 +726,9 @@ impl IguanaEnvironment {
     pub fn read_memory(&self, address: u32) -> Result<u32, LibiguanaError> {
         let mut process = self.jimulator_process.lock().unwrap();
 
-        // Write memory transfer command (mem space, read, 32 bit)
-        ReaderWriter::write(&[0b01_00_1_010], &mut process)?;
+        // Write memory transfer command: 01 (memory transfer), 00 (mem space), 1 (read), 010 (32
+        // bit)
+        ReaderWriter::write(&[0b0100_1010], &mut process)?;
 
         // Write address
         ReaderWriter::write(&address.to_le_bytes(), &mut process)?;
@@ -245,11 +742,42 @@ impl IguanaEnvironment {
         Ok(u32::from_le_bytes(buf))
     }
 
+    /// Reads `length` bytes of memory, starting at `address`.
+    pub fn read_memory_bytes(&self, address: u32, length: u32) -> Result<Vec<u8>, LibiguanaError> {
+        let mut process = self.jimulator_process.lock().unwrap();
+
+        let mut output = Vec::with_capacity(length as usize);
+
+        // jimulator takes the length as a u16, so large reads have to be split up
+        while (output.len() as u32) < length {
+            let chunk_address = address.wrapping_add(output.len() as u32);
+            let chunk_length = (length - output.len() as u32).min(u16::MAX as u32) as u16;
+
+            // Write memory transfer command: 01 (memory transfer), 00 (mem space), 1 (read), 000
+            // (8 bit)
+            ReaderWriter::write(&[0b0100_1000], &mut process)?;
+
+            // Write address
+            ReaderWriter::write(&chunk_address.to_le_bytes(), &mut process)?;
+
+            // Write length
+            ReaderWriter::write(&chunk_length.to_le_bytes(), &mut process)?;
+
+            let mut buf = vec![0; chunk_length as usize];
+            ReaderWriter::read_exact(&mut buf, &mut process)?;
+
+            output.append(&mut buf);
+        }
+
+        Ok(output)
+    }
+
     pub fn registers(&self) -> Result<Registers, LibiguanaError> {
         let mut process = self.jimulator_process.lock().unwrap();
 
-        // Write memory transfer command (reg space, read, 32 bit)
-        ReaderWriter::write(&[0b01_01_1_010], &mut process)?;
+        // Write memory transfer command: 01 (memory transfer), 01 (reg space), 1 (read), 010 (32
+        // bit)
+        ReaderWriter::write(&[0b0101_1010], &mut process)?;
 
         // Write address (0, it's what KoMo2 does)
         ReaderWriter::write(&0_u32.to_le_bytes(), &mut process)?;
@@ -299,6 +827,112 @@ impl IguanaEnvironment {
         Ok(registers)
     }
 
+    /// Loads a new version of the current .kmd file, only writing the bytes that differ from the
+    /// currently loaded one. Breakpoints are moved with their label if code has shifted, so that
+    /// they stay on the same instruction. If no .kmd file is loaded, every byte is written.
+    pub fn reload_kmd(&self, new_kmd: &str) -> Result<ReloadReport, LibiguanaError> {
+        let mut current_kmd = self.current_kmd.lock().unwrap();
+
+        let new_kmd = parse_kmd(new_kmd)
+            .map_err(|_| LibiguanaError::ParseError)?
+            .1
+            .into_iter()
+            .map(KmdparseToken::from)
+            .collect::<Vec<_>>();
+
+        let old_kmd = current_kmd.clone().unwrap_or_default();
+
+        let old_image = old_kmd.image();
+        let new_image = new_kmd.image();
+
+        // Group the changed bytes into contiguous runs so that each run is one write
+        let mut runs: Vec<(u32, Vec<u8>)> = Vec::new();
+        let mut bytes_written: u32 = 0;
+
+        for (address, byte) in &new_image {
+            if old_image.get(address) == Some(byte) {
+                continue;
+            }
+
+            bytes_written += 1;
+
+            match runs.last_mut() {
+                Some((start, bytes))
+                    if start.wrapping_add(bytes.len() as u32) == *address
+                        && bytes.len() < u16::MAX as usize =>
+                {
+                    bytes.push(*byte)
+                }
+                _ => runs.push((*address, vec![*byte])),
+            }
+        }
+
+        for (address, bytes) in &runs {
+            self.write_memory(bytes, *address)?;
+        }
+
+        let moved_symbols = new_kmd
+            .labels()
+            .into_iter()
+            .filter_map(|new_label| {
+                let old_label = old_kmd.label_named(&new_label.name)?;
+
+                (old_label.memory_address != new_label.memory_address).then(|| MovedSymbol {
+                    name: new_label.name.clone(),
+                    old_address: old_label.memory_address,
+                    new_address: new_label.memory_address,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut moved_breakpoints = Vec::new();
+        let mut orphaned_breakpoints = Vec::new();
+
+        for old_address in self.traps().into_keys() {
+            // Breakpoints before the first label have nothing to follow, so they stay put
+            let Some(old_label) = old_kmd.covering_label(old_address) else {
+                continue;
+            };
+
+            match new_kmd.label_named(&old_label.name) {
+                Some(new_label) if new_label.memory_address != old_label.memory_address => {
+                    moved_breakpoints.push(MovedBreakpoint {
+                        old_address,
+                        new_address: new_label
+                            .memory_address
+                            .wrapping_add(old_address - old_label.memory_address),
+                    })
+                }
+                Some(_) => {}
+                None => orphaned_breakpoints.push(old_address),
+            }
+        }
+
+        // All of the old breakpoints are removed first, in case a breakpoint moves to where
+        // another one used to be
+        for moved_breakpoint in &moved_breakpoints {
+            self.remove_breakpoint(moved_breakpoint.old_address)?;
+        }
+
+        for moved_breakpoint in &moved_breakpoints {
+            if !self.traps().contains_key(&moved_breakpoint.new_address) {
+                self.create_breakpoint(moved_breakpoint.new_address)?;
+            }
+        }
+
+        let is_hot_patch_safe = moved_symbols.is_empty() && old_image.keys().eq(new_image.keys());
+
+        *current_kmd = Some(new_kmd);
+
+        Ok(ReloadReport {
+            bytes_written,
+            moved_symbols,
+            moved_breakpoints,
+            orphaned_breakpoints,
+            is_hot_patch_safe,
+        })
+    }
+
     pub fn remove_breakpoint(&self, memory_address: u32) -> Result<(), LibiguanaError> {
         let mut process = self.jimulator_process.lock().unwrap();
         let mut traps = self.traps.lock().unwrap();
@@ -308,9 +942,7 @@ impl IguanaEnvironment {
             .remove(&memory_address)
             .ok_or(LibiguanaError::NoTrapForAddress(memory_address))?;
 
-        // Send word A (all 0s - disable)
-        ReaderWriter::write(&[0; 4], &mut process)?;
-        ReaderWriter::write(&(trap_number as u32).to_le_bytes(), &mut process)?;
+        Self::clear_trap(trap_number, &mut process)?;
 
         used_trap_numbers[trap_number as usize] = false;
 
@@ -318,113 +950,828 @@ impl IguanaEnvironment {
     }
 
     pub fn reset(&self) -> Result<(), LibiguanaError> {
+        let swi_handlers = self.swi_handlers.lock().unwrap();
+        let exception_trap = self.exception_trap.lock().unwrap();
         let mut process = self.jimulator_process.lock().unwrap();
         let mut traps = self.traps.lock().unwrap();
         let mut used_trap_numbers = self.used_trap_numbers.lock().unwrap();
 
         ReaderWriter::write(&[0b0000_0100], &mut process)?;
 
+        for trap_number in traps.values() {
+            Self::clear_trap(*trap_number, &mut process)?;
+        }
+
         traps.clear();
         *used_trap_numbers = [false; u8::MAX as usize];
+        *self.reported_exception.lock().unwrap() = None;
+
+        // The SWI handlers and exception reporting are kept, and jimulator keeps their traps
+        // through a reset
+        for trap_number in swi_handlers.trap_number.into_iter().chain(*exception_trap) {
+            used_trap_numbers[trap_number as usize] = true;
+        }
 
         Ok(())
     }
 
-    /// Starts execution, with the given step limit. If the step limit is 0, the emulator will
-    /// execute indefinitely.
-    pub fn start_execution(&self, steps: u32) -> Result<(), LibiguanaError> {
-        let mut process = self.jimulator_process.lock().unwrap();
+    /// Restarts the loaded program from a clean state. Unlike [`Self::reset`], this rewrites the
+    /// original program image (so any data the last run changed is restored), resets the SP and
+    /// CPSR to what jimulator starts with, and keeps every breakpoint.
+    pub fn restart(&self) -> Result<(), LibiguanaError> {
+        let current_kmd = self.current_kmd.lock().unwrap();
 
-        ReaderWriter::write(&[0b1011_0000], &mut process)?;
-        ReaderWriter::write(&steps.to_le_bytes(), &mut process)?;
+        let kmd = current_kmd.as_ref().ok_or(LibiguanaError::NoKmdLoaded)?;
 
-        Ok(())
-    }
+        {
+            let mut process = self.jimulator_process.lock().unwrap();
+
+            ReaderWriter::write(&[0b0000_0100], &mut process)?;
+        }
+
+        *self.reported_exception.lock().unwrap() = None;
+
+        for token in kmd {
+            if let KmdparseToken::Line { line } = token {
+                if let (Some(word), Some(memory_address)) = (&line.word, line.memory_address) {
+                    self.write_memory(word.bytes(), memory_address)?;
+                }
+            }
+        }
 
-    pub fn stop_execution(&self) -> Result<(), LibiguanaError> {
         let mut process = self.jimulator_process.lock().unwrap();
+        let traps = self.traps.lock().unwrap();
 
-        ReaderWriter::write(&[0b0010_0001], &mut process)?;
+        // The CPSR has to be written first so that the SP is written to the right mode's bank
+        Self::write_register_raw(CPSR_REGISTER, INITIAL_CPSR, &mut process)?;
+        Self::write_register_raw(SP_REGISTER, INITIAL_SP, &mut process)?;
+
+        for (memory_address, trap_number) in traps.iter() {
+            Self::define_trap(*trap_number, *memory_address, &mut process)?;
+        }
 
         Ok(())
     }
 
-    pub fn terminal_messages(&self) -> Result<Vec<u8>, LibiguanaError> {
-        let mut process = self.jimulator_process.lock().unwrap();
+    /// Starts execution, with the given step limit. If the step limit is 0, the emulator will
+    /// execute indefinitely.
+    pub fn start_execution(&self, steps: u32) -> Result<(), LibiguanaError> {
+        let run_end = match steps {
+            0 => None,
+            steps => Some(self.status()?.steps_since_reset.wrapping_add(steps)),
+        };
+
+        *self.run_end.lock().unwrap() = run_end;
 
-        let mut length = 1;
-        let mut output = Vec::new();
+        self.resume()
+    }
 
-        while length != 0 {
-            ReaderWriter::write(&[0b0001_0011, 0, 32], &mut process)?;
+    /// Runs the program for `steps` steps (or until it stops, if `steps` is 0), and waits for it
+    /// to stop or to wait for input. See [`Self::wait_until_stopped`].
+    pub fn run_until_stopped(
+        &self,
+        steps: u32,
+        timeout_ms: u32,
+    ) -> Result<RunOutcome, LibiguanaError> {
+        self.start_execution(steps)?;
 
-            let mut len_buf = [0; 1];
+        self.wait_until_stopped(timeout_ms)
+    }
 
-            ReaderWriter::read_exact(&mut len_buf, &mut process)?;
+    /// Waits up to `timeout_ms` for the running program to stop, or to get stuck waiting for
+    /// input. A program that is waiting for input is still running as far as jimulator is
+    /// concerned, and carries on by itself once it is sent some.
+    pub fn wait_until_stopped(&self, timeout_ms: u32) -> Result<RunOutcome, LibiguanaError> {
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.into());
 
-            length = len_buf[0];
+        loop {
+            let state = self.status()?;
 
-            if length == 0 {
-                break;
+            if !state.status.is_running() && self.service_swi()? {
+                continue;
             }
 
-            let mut buf = vec![0; length as usize];
+            if state.status == Status::Breakpoint {
+                if let Some(report) = self.unreported_exception()? {
+                    *self.reported_exception.lock().unwrap() =
+                        Some((report.clone(), state.steps_since_reset));
 
-            ReaderWriter::read_exact(&mut buf, &mut process)?;
+                    return Ok(RunOutcome::Exception { report });
+                }
 
-            output.append(&mut buf);
-        }
+                if self.pass_through_vectors()? {
+                    continue;
+                }
+            }
 
-        Ok(output)
-    }
+            if state.status == Status::Stopped && self.service_interrupt_timer(&state)? {
+                continue;
+            }
 
-    pub fn status(&self) -> Result<BoardState, LibiguanaError> {
-        let mut process = self.jimulator_process.lock().unwrap();
+            if state.status == Status::Watchpoint && self.service_peripherals()? {
+                continue;
+            }
 
-        ReaderWriter::write(&[0b0010_0000], &mut process)?;
+            if !state.status.is_running() {
+                return Ok(RunOutcome::Stopped { state });
+            }
 
-        let mut buf = [0; 9];
+            if self.awaiting_input()? {
+                return Ok(RunOutcome::AwaitingInput);
+            }
 
-        ReaderWriter::read_exact(&mut buf, &mut process)?;
+            if Instant::now() >= deadline {
+                return Ok(RunOutcome::TimedOut);
+            }
 
-        let steps_remaining = u32::from_le_bytes(buf[1..5].try_into()?);
-        let steps_since_reset = u32::from_le_bytes(buf[5..9].try_into()?);
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
 
-        let status = BoardState {
-            status: Status::try_from(buf[0]).map_err(|_| LibiguanaError::InvalidStatus(buf[0]))?,
-            steps_remaining,
-            steps_since_reset,
-        };
+    pub fn stop_execution(&self) -> Result<(), LibiguanaError> {
+        let mut process = self.jimulator_process.lock().unwrap();
 
-        Ok(status)
-    }
+        ReaderWriter::write(&[0b0010_0001], &mut process)?;
 
-    pub fn traps(&self) -> HashMap<u32, u8> {
-        self.traps.lock().unwrap().clone()
+        Ok(())
     }
 
-    pub fn write_to_terminal(&self, message: &[u8]) -> Result<(), LibiguanaError> {
-        let mut process = self.jimulator_process.lock().unwrap();
-
-        // jimulator only takes one byte as length, so we have to chunk the input into chunks of 256
-        let chunks = message.chunks(u8::MAX as usize);
+    /// Whether the program is stuck in `SWI 1`, waiting for a character to be written to terminal
+    /// 0. jimulator carries on reporting that it is running while it waits, so this checks that
+    /// the PC is on a `SWI 1` and that neither the PC nor the step count move between two looks at
+    /// the board. jimulator runs a step between every command it gets, so a running program
+    /// always moves one of them.
+    pub fn awaiting_input(&self) -> Result<bool, LibiguanaError> {
+        let before = self.status()?;
 
-        for chunk in chunks {
-            ReaderWriter::write(&[0b0001_0010], &mut process)?;
+        if !before.status.is_running() {
+            return Ok(false);
+        }
 
-            // Terminal 0
-            ReaderWriter::write(&[0], &mut process)?;
+        let pc = self.registers()?.pc;
 
-            // Casting here should be fine, a chunk can't be bigger than u8::MAX
-            ReaderWriter::write(&[chunk.len() as u8], &mut process)?;
+        let is_input_swi = if self.cpsr()? & CPSR_THUMB_BIT != 0 {
+            // jimulator winds the PC back by 8 while it waits, even in Thumb state, which leaves it
+            // 4 bytes before the SWI
+            let bytes = self.read_memory_bytes(pc.wrapping_add(4), 2)?;
 
-            ReaderWriter::write(chunk, &mut process)?;
+            u16::from_le_bytes([bytes[0], bytes[1]]) == THUMB_INPUT_SWI
+        } else {
+            self.read_memory(pc)? & 0x0FFF_FFFF == ARM_INPUT_SWI
+        };
 
-            // jimulator returns 0 after every write for some reason
-            ReaderWriter::read_exact(&mut [0], &mut process)?;
+        if !is_input_swi {
+            return Ok(false);
         }
 
-        Ok(())
+        let after = self.status()?;
+
+        Ok(after.status == before.status
+            && after.steps_since_reset == before.steps_since_reset
+            && self.registers()?.pc == pc)
+    }
+
+    /// Whether the program has started or stopped waiting for input since the last call, so that
+    /// the UI can prompt for input once rather than on every poll. Returns `None` if nothing has
+    /// changed.
+    pub fn poll_input_event(&self) -> Result<Option<InputEvent>, LibiguanaError> {
+        let mut was_awaiting_input = self.was_awaiting_input.lock().unwrap();
+
+        let is_awaiting_input = self.awaiting_input()?;
+
+        if is_awaiting_input == *was_awaiting_input {
+            return Ok(None);
+        }
+
+        *was_awaiting_input = is_awaiting_input;
+
+        Ok(Some(if is_awaiting_input {
+            InputEvent::AwaitingInput
+        } else {
+            InputEvent::InputReceived
+        }))
+    }
+
+    /// Has `handler` service `SWI number` from now on, in place of whatever is at the SWI vector.
+    /// This works by trapping the SWI vector, which [`Self::wait_until_stopped`] (and
+    /// so [`Self::run_until_stopped`], [`Interaction`] and [`Self::call_function`]) deal with
+    /// automatically. Anything that runs the program some other way has to call
+    /// [`Self::service_swi`] when it stops. jimulator's own SWIs never reach the vector, so they
+    /// don't stop the program.
+    pub fn register_swi_handler(
+        &self,
+        number: u32,
+        handler: Box<dyn SwiHandler>,
+    ) -> Result<(), LibiguanaError> {
+        if number <= LAST_JIMULATOR_SWI {
+            return Err(LibiguanaError::SwiHandledByJimulator(number));
+        }
+
+        let mut swi_handlers = self.swi_handlers.lock().unwrap();
+
+        if swi_handlers.trap_number.is_none() {
+            let mut process = self.jimulator_process.lock().unwrap();
+            let mut used_trap_numbers = self.used_trap_numbers.lock().unwrap();
+
+            let trap_number = Self::allocate_trap_number(&mut used_trap_numbers)?;
+
+            Self::define_trap(trap_number, SWI_VECTOR, &mut process)?;
+
+            swi_handlers.trap_number = Some(trap_number);
+        }
+
+        swi_handlers.handlers.insert(number, Arc::from(handler));
+
+        Ok(())
+    }
+
+    pub fn remove_swi_handler(&self, number: u32) -> Result<(), LibiguanaError> {
+        let mut swi_handlers = self.swi_handlers.lock().unwrap();
+
+        swi_handlers.handlers.remove(&number);
+
+        if !swi_handlers.handlers.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(trap_number) = swi_handlers.trap_number.take() {
+            let mut process = self.jimulator_process.lock().unwrap();
+            let mut used_trap_numbers = self.used_trap_numbers.lock().unwrap();
+
+            Self::clear_trap(trap_number, &mut process)?;
+            used_trap_numbers[trap_number as usize] = false;
+        }
+
+        Ok(())
+    }
+
+    /// Makes `SWI number` print the character in R0 to terminal `device`, like `SWI 0` does for
+    /// terminal 0. jimulator's own SWIs only ever write to terminal 0, so this is how a program
+    /// writes to terminal 1 (for debug logging in a separate pane, say). It is a SWI handler, so
+    /// see [`Self::register_swi_handler`] for what that involves, and use
+    /// [`Self::remove_swi_handler`] to undo it.
+    pub fn register_terminal_swi(&self, number: u32, device: u8) -> Result<(), LibiguanaError> {
+        let terminal = self.terminal(device)?;
+
+        self.register_swi_handler(
+            number,
+            Box::new(move |_, registers: Registers| {
+                terminal.push_output(&[registers.r0 as u8]);
+                registers
+            }),
+        )
+    }
+
+    /// If the program has stopped on its way into the SWI vector for a SWI with a registered
+    /// handler, returns from the SWI, runs the handler and carries on running. Every other SWI
+    /// is left for jimulator, and the program carries on into it. Returns whether the program was
+    /// set running again, which it isn't if it stopped at the end of a step count or the SWI
+    /// returns to one of the user's breakpoints.
+    pub fn service_swi(&self) -> Result<bool, LibiguanaError> {
+        let state = self.status()?;
+
+        if state.status.is_running()
+            || self.swi_handlers.lock().unwrap().trap_number.is_none()
+            || self.registers()?.pc != SWI_VECTOR
+            || self.traps().contains_key(&SWI_VECTOR)
+        {
+            return Ok(false);
+        }
+
+        let handler = self.pending_swi()?.and_then(|number| {
+            let swi_handlers = self.swi_handlers.lock().unwrap();
+
+            Some((number, swi_handlers.handlers.get(&number)?.clone()))
+        });
+
+        let Some((number, handler)) = handler else {
+            // Only the trap on the vector stops the program there without the run ending
+            if state.status != Status::Breakpoint || self.unreported_exception()?.is_some() {
+                return Ok(false);
+            }
+
+            self.resume()?;
+
+            return Ok(true);
+        };
+
+        {
+            let mut process = self.jimulator_process.lock().unwrap();
+
+            let spsr = Self::read_register_raw(SPSR_REGISTER, &mut process)?;
+            let lr = Self::read_register_raw(LR_REGISTER as u32, &mut process)?;
+
+            // The same as `MOVS PC, LR`. The CPSR goes first, so that the caller's bank is
+            // switched back in.
+            Self::write_register_raw(CPSR_REGISTER, spsr, &mut process)?;
+            Self::write_register_raw(PC_REGISTER as u32, lr, &mut process)?;
+        }
+
+        let registers = handler.handle_swi(number, self.registers()?);
+
+        for (register, value) in registers
+            .as_array()
+            .into_iter()
+            .enumerate()
+            .take(PC_REGISTER as usize)
+        {
+            self.write_register(register as u8, value)?;
+        }
+
+        // Having stopped at the end of a step count, the run is over or the interrupt timer is
+        // due to tick, which the caller deals with
+        if state.status != Status::Breakpoint
+            || *self.run_end.lock().unwrap() == Some(state.steps_since_reset)
+            || self.traps().contains_key(&registers.pc)
+        {
+            return Ok(false);
+        }
+
+        self.resume()?;
+
+        Ok(true)
+    }
+
+    /// Raises an IRQ. See [`Self::raise_interrupt`].
+    pub fn raise_irq(&self) -> Result<bool, LibiguanaError> {
+        self.raise_interrupt(Interrupt::Irq)
+    }
+
+    /// Raises an FIQ. See [`Self::raise_interrupt`].
+    pub fn raise_fiq(&self) -> Result<bool, LibiguanaError> {
+        self.raise_interrupt(Interrupt::Fiq)
+    }
+
+    /// Has the processor take `interrupt` before its next instruction, as long as the CPSR
+    /// doesn't have it disabled: the CPSR is saved to the interrupt mode's SPSR, the mode is
+    /// switched (disabling the interrupt, and Thumb), the LR is set so that `SUBS PC, LR, #4`
+    /// returns, and the PC is set to the vector. A handler that enables interrupts again can be
+    /// interrupted in turn. A running program is paused while this happens. Returns whether the
+    /// interrupt was taken.
+    pub fn raise_interrupt(&self, interrupt: Interrupt) -> Result<bool, LibiguanaError> {
+        self.while_paused(|| self.enter_interrupt(interrupt))
+    }
+
+    /// Raises `interrupt` every `period` steps from now, replacing any timer there already is
+    /// (apart from a `Timer` peripheral's, which runs alongside it).
+    /// Ticks are raised by [`Self::wait_until_stopped`] (and so [`Self::run_until_stopped`],
+    /// [`Interaction`] and [`ProgramTest`](testing::ProgramTest)), which stop jimulator at each
+    /// one. A tick that the CPSR has the interrupt disabled for is missed.
+    pub fn start_interrupt_timer(
+        &self,
+        interrupt: Interrupt,
+        period: u32,
+    ) -> Result<(), LibiguanaError> {
+        if period == 0 {
+            return Err(LibiguanaError::InvalidTimerPeriod);
+        }
+
+        self.while_paused(|| {
+            let steps_since_reset = self.status()?.steps_since_reset;
+
+            *self.interrupt_timer.lock().unwrap() =
+                Some(InterruptTimer::new(interrupt, period, steps_since_reset));
+
+            Ok(())
+        })
+    }
+
+    pub fn stop_interrupt_timer(&self) -> Result<(), LibiguanaError> {
+        self.while_paused(|| {
+            *self.interrupt_timer.lock().unwrap() = None;
+
+            Ok(())
+        })
+    }
+
+    /// Maps a `kind` peripheral into memory at `address` (see the table in the `peripheral`
+    /// module for its registers). `observer` is told about every access the program makes to it,
+    /// which [`Self::wait_until_stopped`] (and so [`Self::run_until_stopped`], [`Interaction`] and
+    /// [`ProgramTest`](testing::ProgramTest)) catch by putting a watchpoint on it. Anything that
+    /// runs the program some other way has to call [`Self::service_peripherals`] when it stops on
+    /// a watchpoint. jimulator only has 4 watchpoints, and every kind of peripheral but buttons
+    /// needs one.
+    pub fn add_peripheral(
+        &self,
+        name: String,
+        kind: PeripheralKind,
+        address: u32,
+        observer: Box<dyn PeripheralObserver>,
+    ) -> Result<(), LibiguanaError> {
+        let mut peripherals = self.peripherals.lock().unwrap();
+
+        let size = kind.register_count() * 4;
+
+        if !address.is_multiple_of(4)
+            || address
+                .checked_add(size)
+                .is_none_or(|end| end > MEMORY_SIZE)
+        {
+            return Err(LibiguanaError::InvalidPeripheralAddress(address));
+        }
+
+        if peripherals.iter().any(|peripheral| peripheral.name == name) {
+            return Err(LibiguanaError::PeripheralAlreadyExists(name));
+        }
+
+        if let Some(peripheral) = peripherals.iter().find(|peripheral| {
+            peripheral.contains(address) || (address..address + size).contains(&peripheral.address)
+        }) {
+            return Err(LibiguanaError::PeripheralOverlaps(peripheral.name.clone()));
+        }
+
+        let watchpoint_number = if kind.is_watched() {
+            let watchpoint_number = (0..MAX_WATCHPOINTS)
+                .find(|number| {
+                    peripherals
+                        .iter()
+                        .all(|peripheral| peripheral.watchpoint_number != Some(*number))
+                })
+                .ok_or(LibiguanaError::TooManyWatchpoints)?;
+
+            let mut process = self.jimulator_process.lock().unwrap();
+
+            Self::define_watchpoint(watchpoint_number, address, size, &mut process)?;
+
+            Some(watchpoint_number)
+        } else {
+            None
+        };
+
+        let mut peripheral =
+            Peripheral::new(name, kind, address, Arc::from(observer), watchpoint_number);
+
+        peripheral.refresh(self.status()?.steps_since_reset);
+        self.write_peripheral_registers(&peripheral)?;
+
+        peripherals.push(peripheral);
+
+        Ok(())
+    }
+
+    /// Unmaps the peripheral called `name`, leaving whatever it last held in memory.
+    pub fn remove_peripheral(&self, name: &str) -> Result<(), LibiguanaError> {
+        let mut peripherals = self.peripherals.lock().unwrap();
+
+        let index = peripherals
+            .iter()
+            .position(|peripheral| peripheral.name == name)
+            .ok_or_else(|| LibiguanaError::NoSuchPeripheral(name.to_string()))?;
+
+        let peripheral = peripherals.remove(index);
+
+        if peripheral.kind == PeripheralKind::Timer {
+            *self.peripheral_timer.lock().unwrap() = None;
+        }
+
+        if let Some(watchpoint_number) = peripheral.watchpoint_number {
+            let mut process = self.jimulator_process.lock().unwrap();
+
+            Self::clear_watchpoint(watchpoint_number, &mut process)?;
+        }
+
+        Ok(())
+    }
+
+    /// The current values of the registers of the peripheral called `name`, for drawing it.
+    pub fn peripheral_registers(&self, name: &str) -> Result<Vec<u32>, LibiguanaError> {
+        let peripherals = self.peripherals.lock().unwrap();
+
+        peripherals
+            .iter()
+            .find(|peripheral| peripheral.name == name)
+            .map(|peripheral| peripheral.registers.clone())
+            .ok_or_else(|| LibiguanaError::NoSuchPeripheral(name.to_string()))
+    }
+
+    /// Sets which of the buttons called `name` are pressed, one bit per button.
+    pub fn set_buttons(&self, name: &str, pressed: u32) -> Result<(), LibiguanaError> {
+        self.update_peripheral(name, PeripheralKind::Buttons, |buttons| {
+            buttons.registers[0] = pressed
+        })
+    }
+
+    /// Queues `data` for the program to read from the UART called `name`.
+    pub fn send_to_uart(&self, name: &str, data: Vec<u8>) -> Result<(), LibiguanaError> {
+        self.update_peripheral(name, PeripheralKind::Uart, |uart| {
+            uart.received.extend(data)
+        })
+    }
+
+    /// If the program has stopped on a peripheral's watchpoint, applies what it did to the
+    /// peripheral, tells the peripheral's observer, and carries on running. Returns whether the
+    /// program was set running again, which it isn't if it ran out of steps.
+    pub fn service_peripherals(&self) -> Result<bool, LibiguanaError> {
+        let state = self.status()?;
+
+        if state.status != Status::Watchpoint {
+            return Ok(false);
+        }
+
+        let accesses = self.peripheral_accesses()?;
+
+        let mut notifications = Vec::new();
+        let mut timer_period = None;
+
+        {
+            let mut peripherals = self.peripherals.lock().unwrap();
+
+            for (index, kind, offset, value) in accesses {
+                let peripheral = &mut peripherals[index];
+
+                let access = match kind {
+                    PeripheralAccessKind::Read => peripheral.read(offset, value),
+                    PeripheralAccessKind::Write => peripheral.write(offset, value),
+                };
+
+                if peripheral.kind == PeripheralKind::Timer
+                    && kind == PeripheralAccessKind::Write
+                    && offset == TIMER_PERIOD_OFFSET
+                {
+                    timer_period = Some(value);
+                }
+
+                notifications.push((peripheral.observer.clone(), peripheral.name.clone(), access));
+            }
+        }
+
+        self.refresh_peripherals()?;
+
+        for (observer, name, access) in notifications {
+            observer.peripheral_accessed(name, access);
+        }
+
+        if let Some(period) = timer_period {
+            *self.peripheral_timer.lock().unwrap() = (period != 0)
+                .then(|| InterruptTimer::new(Interrupt::Irq, period, state.steps_since_reset));
+        }
+
+        // jimulator stops as soon as the access is made, but still finishes the instruction,
+        // without counting it as a step, so the run has to end a step sooner
+        if let Some(run_end) = self.run_end.lock().unwrap().as_mut() {
+            *run_end = run_end.wrapping_sub(1);
+
+            if *run_end == state.steps_since_reset {
+                return Ok(false);
+            }
+        }
+
+        self.resume()?;
+
+        Ok(true)
+    }
+
+    /// Turns on (or off) stopping the program when it raises an exception, rather than letting it
+    /// carry on into the vector. This works by trapping every instruction fetched from the
+    /// vectors, which [`Self::wait_until_stopped`] checks against the processor's mode to tell
+    /// an exception apart from code that just happens to be there, returning
+    /// [`RunOutcome::Exception`] for the former. Interrupts raised by the library itself go
+    /// straight into their handler, and aren't reported.
+    pub fn set_exception_reporting(&self, enabled: bool) -> Result<(), LibiguanaError> {
+        let mut exception_trap = self.exception_trap.lock().unwrap();
+
+        if enabled == exception_trap.is_some() {
+            return Ok(());
+        }
+
+        let mut process = self.jimulator_process.lock().unwrap();
+        let mut used_trap_numbers = self.used_trap_numbers.lock().unwrap();
+
+        match exception_trap.take() {
+            Some(trap_number) => {
+                Self::clear_trap(trap_number, &mut process)?;
+                used_trap_numbers[trap_number as usize] = false;
+            }
+            None => {
+                let trap_number = Self::allocate_trap_number(&mut used_trap_numbers)?;
+
+                Self::define_masked_trap(
+                    trap_number,
+                    0,
+                    EXCEPTION_VECTORS_MASK,
+                    0,
+                    0,
+                    &mut process,
+                )?;
+
+                *exception_trap = Some(trap_number);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// If exceptions are being reported and the program has stopped on its way into one,
+    /// describes it.
+    pub fn exception_report(&self) -> Result<Option<ExceptionReport>, LibiguanaError> {
+        if self.exception_trap.lock().unwrap().is_none()
+            || self.status()?.status != Status::Breakpoint
+        {
+            return Ok(None);
+        }
+
+        let registers = self.registers()?;
+
+        let Some(kind) = ExceptionKind::at_vector(registers.pc) else {
+            return Ok(None);
+        };
+
+        let (cpsr, previous_cpsr) = {
+            let mut process = self.jimulator_process.lock().unwrap();
+
+            (
+                Self::read_register_raw(CPSR_REGISTER, &mut process)?,
+                Self::read_register_raw(SPSR_REGISTER, &mut process)?,
+            )
+        };
+
+        // Only an exception switches to its mode on the way to the vector
+        if ProcessorMode::from_cpsr(cpsr) != Some(kind.mode()) {
+            return Ok(None);
+        }
+
+        let was_thumb = previous_cpsr & CPSR_THUMB_BIT != 0;
+        let address = registers.r14.wrapping_sub(kind.return_offset(was_thumb));
+
+        // The SWI vector is also where supervisor mode code ends up by branching to it
+        if kind == ExceptionKind::Swi {
+            let instruction = self.read_instruction(address, was_thumb)?;
+
+            if swi_handler::decode_swi(instruction, was_thumb, previous_cpsr).is_none() {
+                return Ok(None);
+            }
+        }
+
+        let source_line = self
+            .current_kmd
+            .lock()
+            .unwrap()
+            .as_deref()
+            .and_then(|kmd| kmd.covering_line(address))
+            .map(|line| line.comment.trim().to_string());
+
+        Ok(Some(ExceptionReport {
+            kind,
+            address,
+            instruction: self.decode_at(address).ok(),
+            source_line,
+            previous_mode: ProcessorMode::from_cpsr(previous_cpsr),
+            previous_cpsr,
+        }))
+    }
+
+    /// Everything the program has written to terminal 0 since the last read.
+    pub fn terminal_messages(&self) -> Result<Vec<u8>, LibiguanaError> {
+        self.terminals[0].read()
+    }
+
+    /// The handle for terminal `device` (0 or 1). See [`Terminal`] for how a program can use
+    /// terminal 1.
+    pub fn terminal(&self, device: u8) -> Result<Arc<Terminal>, LibiguanaError> {
+        self.terminals
+            .get(device as usize)
+            .cloned()
+            .ok_or(LibiguanaError::InvalidTerminal(device))
+    }
+
+    /// A text stream over terminal `device`. Each stream holds its own partly decoded output, so
+    /// keep hold of one rather than asking for a new one for each read.
+    pub fn terminal_stream(
+        &self,
+        device: u8,
+        interpret_ansi: bool,
+    ) -> Result<Arc<TerminalStream>, LibiguanaError> {
+        Ok(Arc::new(TerminalStream::new(
+            self.terminal(device)?,
+            interpret_ansi,
+        )))
+    }
+
+    pub fn status(&self) -> Result<BoardState, LibiguanaError> {
+        let mut process = self.jimulator_process.lock().unwrap();
+
+        ReaderWriter::write(&[0b0010_0000], &mut process)?;
+
+        let mut buf = [0; 9];
+
+        ReaderWriter::read_exact(&mut buf, &mut process)?;
+
+        let steps_remaining = u32::from_le_bytes(buf[1..5].try_into()?);
+        let steps_since_reset = u32::from_le_bytes(buf[5..9].try_into()?);
+
+        let status = BoardState {
+            status: Status::try_from(buf[0]).map_err(|_| LibiguanaError::InvalidStatus(buf[0]))?,
+            steps_remaining,
+            steps_since_reset,
+        };
+
+        Ok(status)
+    }
+
+    pub fn traps(&self) -> HashMap<u32, u8> {
+        self.traps.lock().unwrap().clone()
+    }
+
+    /// Reads back every line of the loaded `.kmd` file from emulator memory, returning the lines
+    /// that don't match. A byte that more than one line covers is checked against the last one,
+    /// which is what loading the file leaves there. If `instructions_only` is true, data lines are skipped - this is useful
+    /// after a run, where data is expected to change but any change to an instruction means the
+    /// program has modified itself or stored into its own code.
+    pub fn verify_kmd(
+        &self,
+        instructions_only: bool,
+    ) -> Result<Vec<MemoryMismatch>, LibiguanaError> {
+        let current_kmd = self.current_kmd.lock().unwrap();
+
+        let kmd = current_kmd.as_ref().ok_or(LibiguanaError::NoKmdLoaded)?;
+
+        // Thumb instructions still get 4-byte words, which overlap the next instruction, so
+        // each byte belongs to the last line that covers it, just like in the image
+        let mut owners = BTreeMap::new();
+
+        for token in kmd {
+            if let KmdparseToken::Line { line } = token {
+                if let (Some(word), Some(memory_address)) = (&line.word, line.memory_address) {
+                    let is_instruction = matches!(word, KmdparseWord::Instruction { .. });
+
+                    for offset in 0..word.bytes().len() as u32 {
+                        owners.insert(
+                            memory_address.wrapping_add(offset),
+                            (memory_address, is_instruction),
+                        );
+                    }
+                }
+            }
+        }
+
+        let image = kmd.image();
+
+        // One read per contiguous run of the image
+        let mut actual = BTreeMap::new();
+        let mut addresses = image.keys().copied().peekable();
+
+        while let Some(start) = addresses.next() {
+            let mut end = start;
+
+            while addresses
+                .next_if(|address| Some(*address) == end.checked_add(1))
+                .is_some()
+            {
+                end += 1;
+            }
+
+            let bytes = self.read_memory_bytes(start, end - start + 1)?;
+
+            actual.extend((start..=end).zip(bytes));
+        }
+
+        let mut lines: BTreeMap<u32, MemoryMismatch> = BTreeMap::new();
+
+        for (address, (memory_address, is_instruction)) in owners {
+            if instructions_only && !is_instruction {
+                continue;
+            }
+
+            let line = lines.entry(memory_address).or_insert(MemoryMismatch {
+                memory_address,
+                expected: Vec::new(),
+                actual: Vec::new(),
+                is_instruction,
+            });
+
+            line.expected.push(image[&address]);
+            line.actual.push(actual[&address]);
+        }
+
+        let mismatches = lines
+            .into_values()
+            .filter(|line| line.expected != line.actual)
+            .collect();
+
+        Ok(mismatches)
+    }
+
+    /// Writes `value` to `register` (0-15) in the current mode's bank.
+    pub fn write_register(&self, register: u8, value: u32) -> Result<(), LibiguanaError> {
+        if register > 15 {
+            return Err(LibiguanaError::InvalidRegister(register));
+        }
+
+        let mut process = self.jimulator_process.lock().unwrap();
+
+        Self::write_register_raw(register as u32, value, &mut process)
+    }
+
+    /// Sends `message` to terminal 0, for the program to read.
+    pub fn write_to_terminal(&self, message: &[u8]) -> Result<(), LibiguanaError> {
+        self.terminals[0].write(message)
+    }
+
+    /// Writes a 32-bit word to memory, updating any instructions in the current KMD that it
+    /// overwrites. Their source lines are replaced with the new instructions' disassembly. Returns
+    /// the word that was there before.
+    pub fn write_word(&self, address: u32, word: u32) -> Result<u32, LibiguanaError> {
+        self.write_instruction(address, &word.to_le_bytes(), None)
     }
 
     fn write_memory(&self, word: &[u8], address: u32) -> Result<(), LibiguanaError> {
@@ -434,8 +1781,9 @@ impl IguanaEnvironment {
             return Ok(());
         }
 
-        // Write memory transfer command (mem space, write, 8 bit)
-        ReaderWriter::write(&[0b01_00_0_000], &mut process)?;
+        // Write memory transfer command: 01 (memory transfer), 00 (mem space), 0 (write), 000 (8
+        // bit)
+        ReaderWriter::write(&[0b0100_0000], &mut process)?;
 
         // Write address
         ReaderWriter::write(&address.to_le_bytes(), &mut process)?;
@@ -451,6 +1799,769 @@ impl IguanaEnvironment {
     }
 }
 
+impl IguanaEnvironment {
+    /// Writes `bytes` to memory at `address`, updating any instructions in the current KMD that
+    /// they overwrite. The one at `address` gets `source_line` as its source line, if given, and
+    /// the rest get their disassembly. Returns the word that was there before.
+    fn write_instruction(
+        &self,
+        address: u32,
+        bytes: &[u8],
+        source_line: Option<&str>,
+    ) -> Result<u32, LibiguanaError> {
+        let old_word = self.read_memory(address)?;
+
+        self.write_memory(bytes, address)?;
+
+        let is_written =
+            |memory_address: u32| (memory_address.wrapping_sub(address) as usize) < bytes.len();
+
+        // The lines whose instructions start in what was written
+        let starts = self
+            .current_kmd
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .filter_map(|token| match token {
+                KmdparseToken::Line { line } => match (&line.word, line.memory_address) {
+                    (Some(KmdparseWord::Instruction { .. }), Some(memory_address))
+                        if is_written(memory_address) =>
+                    {
+                        Some(memory_address)
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut sources = HashMap::new();
+
+        for start in starts {
+            let source = match source_line {
+                Some(source_line) if start == address => source_line.to_string(),
+                _ => self.source_at(start)?,
+            };
+
+            sources.insert(start, source);
+        }
+
+        let mut current_kmd = self.current_kmd.lock().unwrap();
+
+        for token in current_kmd.iter_mut().flatten() {
+            let KmdparseToken::Line { line } = token else {
+                continue;
+            };
+
+            let (Some(KmdparseWord::Instruction { instruction }), Some(memory_address)) =
+                (&mut line.word, line.memory_address)
+            else {
+                continue;
+            };
+
+            // Thumb instructions still get 4-byte words, so a write can cover part of one
+            for (offset, byte) in instruction.iter_mut().enumerate() {
+                let byte_address = memory_address.wrapping_add(offset as u32);
+
+                if is_written(byte_address) {
+                    *byte = bytes[byte_address.wrapping_sub(address) as usize];
+                }
+            }
+
+            if let Some(source) = sources.get(&memory_address) {
+                line.set_source(source);
+            }
+        }
+
+        Ok(old_word)
+    }
+
+    /// The instruction at `address` as a source line, falling back to a `DEFW` (or `DEFH` for
+    /// Thumb) if it doesn't disassemble.
+    fn source_at(&self, address: u32) -> Result<String, LibiguanaError> {
+        if let Ok(source) = self.disassemble_at(address) {
+            return Ok(source);
+        }
+
+        if self.is_thumb_at(address)? {
+            let bytes = self.read_memory_bytes(address, 2)?;
+
+            return Ok(format!(
+                "DEFH {:#06x}",
+                u16::from_le_bytes([bytes[0], bytes[1]])
+            ));
+        }
+
+        Ok(format!("DEFW {:#010x}", self.read_memory(address)?))
+    }
+
+    /// Whether the code at `address` is Thumb code. The current KMD's labels are used if there are
+    /// any, since the CPSR only says what mode the processor is in right now.
+    fn is_thumb_at(&self, address: u32) -> Result<bool, LibiguanaError> {
+        let is_thumb_label = self
+            .current_kmd
+            .lock()
+            .unwrap()
+            .as_deref()
+            .and_then(|kmd| kmd.covering_label(address))
+            .map(|label| label.is_thumb);
+
+        match is_thumb_label {
+            Some(is_thumb) => Ok(is_thumb),
+            None => Ok(self.cpsr()? & CPSR_THUMB_BIT != 0),
+        }
+    }
+
+    /// Calls the function at `address`, saving and restoring everything around it. See
+    /// [`Self::call_function_at`].
+    fn call(
+        &self,
+        address: u32,
+        is_thumb: bool,
+        arguments: &[u32],
+        timeout_ms: u32,
+    ) -> Result<FunctionReturn, LibiguanaError> {
+        if self.status()?.status.is_running() {
+            return Err(LibiguanaError::ProgramRunning);
+        }
+
+        let saved_registers = self.registers()?.as_array();
+        let saved_cpsr = self.cpsr()?;
+
+        let saved_sp = saved_registers[SP_REGISTER as usize];
+        let stacked_arguments = arguments.get(ARGUMENT_REGISTERS..).unwrap_or_default();
+
+        let stack_size = stacked_arguments.len() as u32 * 4;
+
+        // jimulator's memory doesn't wrap for the program, so a stack that would go below 0 or
+        // above the top of memory reads back garbage
+        let stack_top = if saved_sp > stack_size && saved_sp <= MEMORY_SIZE {
+            saved_sp
+        } else {
+            MEMORY_SIZE
+        };
+
+        // The stack has to be 8-byte aligned when a function is called
+        let sp = stack_top.saturating_sub(stack_size) & !0b111;
+        let saved_stack = self.read_memory_bytes(sp, stack_top - sp)?;
+
+        // Leave any breakpoint the user already had there alone
+        let adds_breakpoint = !self.traps().contains_key(&RETURN_SENTINEL);
+
+        if adds_breakpoint {
+            self.create_breakpoint(RETURN_SENTINEL)?;
+        }
+
+        let result = self.run_function(address, is_thumb, arguments, sp, timeout_ms);
+
+        if self.status()?.status.is_running() {
+            self.stop_execution()?;
+        }
+
+        self.write_memory(&saved_stack, sp)?;
+
+        {
+            let mut process = self.jimulator_process.lock().unwrap();
+
+            // The CPSR has to be written first so that the SP is written to the right mode's bank
+            Self::write_register_raw(CPSR_REGISTER, saved_cpsr, &mut process)?;
+        }
+
+        for (register, value) in saved_registers.into_iter().enumerate() {
+            self.write_register(register as u8, value)?;
+        }
+
+        if adds_breakpoint {
+            self.remove_breakpoint(RETURN_SENTINEL)?;
+        }
+
+        result
+    }
+
+    /// Sets up the arguments, runs the function at `address` and reads its return value.
+    fn run_function(
+        &self,
+        address: u32,
+        is_thumb: bool,
+        arguments: &[u32],
+        sp: u32,
+        timeout_ms: u32,
+    ) -> Result<FunctionReturn, LibiguanaError> {
+        let stacked_arguments = arguments
+            .iter()
+            .skip(ARGUMENT_REGISTERS)
+            .flat_map(|argument| argument.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        self.write_memory(&stacked_arguments, sp)?;
+
+        let cpsr = self.cpsr()?;
+
+        {
+            let mut process = self.jimulator_process.lock().unwrap();
+
+            let cpsr = if is_thumb {
+                cpsr | CPSR_THUMB_BIT
+            } else {
+                cpsr & !CPSR_THUMB_BIT
+            };
+
+            Self::write_register_raw(CPSR_REGISTER, cpsr, &mut process)?;
+        }
+
+        for (register, argument) in arguments.iter().take(ARGUMENT_REGISTERS).enumerate() {
+            self.write_register(register as u8, *argument)?;
+        }
+
+        self.write_register(SP_REGISTER as u8, sp)?;
+        self.write_register(LR_REGISTER, RETURN_SENTINEL)?;
+        self.write_register(PC_REGISTER, address)?;
+
+        let steps_before = self.status()?.steps_since_reset;
+
+        let outcome = self.run_until_stopped(0, timeout_ms)?;
+
+        match &outcome {
+            RunOutcome::Stopped { state }
+                if state.status == Status::Breakpoint
+                    && self.registers()?.pc == RETURN_SENTINEL =>
+            {
+                let registers = self.registers()?;
+
+                Ok(FunctionReturn {
+                    r0: registers.r0,
+                    r1: registers.r1,
+                    steps: state.steps_since_reset.wrapping_sub(steps_before),
+                })
+            }
+            _ => Err(LibiguanaError::FunctionDidNotReturn(outcome.description())),
+        }
+    }
+
+    /// Marks the lowest free trap number as used and returns it.
+    fn allocate_trap_number(
+        used_trap_numbers: &mut [bool; u8::MAX as usize],
+    ) -> Result<u8, LibiguanaError> {
+        let trap_number = used_trap_numbers
+            .iter()
+            .take(MAX_BREAKPOINTS)
+            .position(|is_used| !is_used)
+            .ok_or(LibiguanaError::TooManyTraps)?;
+
+        used_trap_numbers[trap_number] = true;
+
+        Ok(trap_number as u8)
+    }
+
+    /// Carries on the current run, as far as its end or the interrupt timer's next tick,
+    /// whichever comes first.
+    fn resume(&self) -> Result<(), LibiguanaError> {
+        // Starting from outside the vectors, the program can only get back to one by raising
+        // the exception again
+        if self.reported_exception.lock().unwrap().is_some()
+            && self.registers()?.pc & EXCEPTION_VECTORS_MASK != 0
+        {
+            *self.reported_exception.lock().unwrap() = None;
+        }
+
+        self.refresh_peripherals()?;
+
+        let run_end = *self.run_end.lock().unwrap();
+        let mut interrupt_timer = self.interrupt_timer.lock().unwrap();
+        let mut peripheral_timer = self.peripheral_timer.lock().unwrap();
+
+        let steps = if run_end.is_some() || interrupt_timer.is_some() || peripheral_timer.is_some()
+        {
+            let steps_since_reset = self.status()?.steps_since_reset;
+
+            let steps_until_end = run_end.map(|run_end| run_end.wrapping_sub(steps_since_reset));
+            let steps_until_tick = interrupt_timer
+                .iter_mut()
+                .chain(peripheral_timer.iter_mut())
+                .map(|timer| timer.steps_until_tick(steps_since_reset))
+                .min();
+
+            // 0 would run indefinitely
+            steps_until_end
+                .into_iter()
+                .chain(steps_until_tick)
+                .min()
+                .map_or(0, |steps| steps.max(1))
+        } else {
+            0
+        };
+
+        drop(interrupt_timer);
+        drop(peripheral_timer);
+
+        let mut process = self.jimulator_process.lock().unwrap();
+
+        ReaderWriter::write(&[0b1011_0000], &mut process)?;
+        ReaderWriter::write(&steps.to_le_bytes(), &mut process)?;
+
+        Ok(())
+    }
+
+    /// Runs `f` with the program paused, if it was running, and carries on running it afterwards.
+    fn while_paused<T>(
+        &self,
+        f: impl FnOnce() -> Result<T, LibiguanaError>,
+    ) -> Result<T, LibiguanaError> {
+        let was_running = self.status()?.status.is_running();
+
+        if was_running {
+            self.pause()?;
+        }
+
+        let result = f();
+
+        // The program may have stopped by itself before it could be paused
+        let state = self.status()?;
+
+        if was_running && state.status == Status::Stopped {
+            self.resume()?;
+        }
+
+        result
+    }
+
+    /// Goes through the architectural entry sequence for `interrupt` if the CPSR allows it.
+    /// Returns whether it did.
+    fn enter_interrupt(&self, interrupt: Interrupt) -> Result<bool, LibiguanaError> {
+        let mut process = self.jimulator_process.lock().unwrap();
+
+        let cpsr = Self::read_register_raw(CPSR_REGISTER, &mut process)?;
+
+        if cpsr & interrupt.disable_bit() != 0 {
+            return Ok(false);
+        }
+
+        let next_instruction = Self::read_register_raw(PC_REGISTER as u32, &mut process)?;
+
+        let interrupt_cpsr = (cpsr & !(CPSR_MODE_MASK | CPSR_THUMB_BIT))
+            | interrupt.mode()
+            | interrupt.entry_disable_bits();
+
+        // The CPSR goes first, so that the SPSR and LR are written to the interrupt mode's bank
+        Self::write_register_raw(CPSR_REGISTER, interrupt_cpsr, &mut process)?;
+        Self::write_register_raw(SPSR_REGISTER, cpsr, &mut process)?;
+        Self::write_register_raw(
+            LR_REGISTER as u32,
+            next_instruction.wrapping_add(INTERRUPT_RETURN_OFFSET),
+            &mut process,
+        )?;
+        Self::write_register_raw(PC_REGISTER as u32, interrupt.vector(), &mut process)?;
+
+        Ok(true)
+    }
+
+    /// If the program has stopped for a tick of the interrupt timer (or a `Timer` peripheral's),
+    /// raises the interrupt and carries on the run, unless it was due to end there anyway. Returns
+    /// whether the program was set running again.
+    fn service_interrupt_timer(&self, state: &BoardState) -> Result<bool, LibiguanaError> {
+        let interrupts = {
+            let mut interrupt_timer = self.interrupt_timer.lock().unwrap();
+            let mut peripheral_timer = self.peripheral_timer.lock().unwrap();
+
+            interrupt_timer
+                .iter_mut()
+                .chain(peripheral_timer.iter_mut())
+                .filter(|timer| timer.next_tick == state.steps_since_reset)
+                .map(|timer| {
+                    timer.next_tick = state.steps_since_reset.wrapping_add(timer.period);
+
+                    timer.interrupt
+                })
+                .collect::<Vec<_>>()
+        };
+
+        if interrupts.is_empty() {
+            return Ok(false);
+        }
+
+        // Both timers can tick at once. A second IRQ is missed, since taking the first one
+        // disables IRQs.
+        for interrupt in interrupts {
+            self.enter_interrupt(interrupt)?;
+        }
+
+        let run_ended = *self.run_end.lock().unwrap() == Some(state.steps_since_reset);
+
+        if run_ended {
+            return Ok(false);
+        }
+
+        self.resume()?;
+
+        Ok(true)
+    }
+
+    /// The number of the SWI the program is entering the SWI vector for, if it is in supervisor
+    /// mode because of one.
+    fn pending_swi(&self) -> Result<Option<u32>, LibiguanaError> {
+        let (cpsr, spsr, lr) = {
+            let mut process = self.jimulator_process.lock().unwrap();
+
+            (
+                Self::read_register_raw(CPSR_REGISTER, &mut process)?,
+                Self::read_register_raw(SPSR_REGISTER, &mut process)?,
+                Self::read_register_raw(LR_REGISTER as u32, &mut process)?,
+            )
+        };
+
+        if ProcessorMode::from_cpsr(cpsr) != Some(ProcessorMode::Supervisor) {
+            return Ok(None);
+        }
+
+        let was_thumb = spsr & CPSR_THUMB_BIT != 0;
+        let address = lr.wrapping_sub(ExceptionKind::Swi.return_offset(was_thumb));
+
+        let instruction = self.read_instruction(address, was_thumb)?;
+
+        Ok(swi_handler::decode_swi(instruction, was_thumb, spsr))
+    }
+
+    /// The instruction at `address`, read as a halfword if it is Thumb.
+    fn read_instruction(&self, address: u32, is_thumb: bool) -> Result<u32, LibiguanaError> {
+        if !is_thumb {
+            return self.read_memory(address);
+        }
+
+        let bytes = self.read_memory_bytes(address, 2)?;
+
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as u32)
+    }
+
+    /// If the program has stopped on the exception vectors without raising an exception (or
+    /// hitting one of the user's breakpoints), carries on running. Returns whether it did.
+    fn pass_through_vectors(&self) -> Result<bool, LibiguanaError> {
+        if self.exception_trap.lock().unwrap().is_none()
+            || self.status()?.status != Status::Breakpoint
+        {
+            return Ok(false);
+        }
+
+        let pc = self.registers()?.pc;
+
+        if pc & EXCEPTION_VECTORS_MASK != 0
+            || self.traps().contains_key(&pc)
+            || self.unreported_exception()?.is_some()
+        {
+            return Ok(false);
+        }
+
+        self.resume()?;
+
+        Ok(true)
+    }
+
+    /// The exception the program has stopped for, unless it is the one that was last reported
+    /// and the program hasn't left its vector since. Only running the instruction at the vector
+    /// (one step) can have brought it straight back there - anything more and the exception could
+    /// have been raised again.
+    fn unreported_exception(&self) -> Result<Option<ExceptionReport>, LibiguanaError> {
+        let Some(report) = self.exception_report()? else {
+            return Ok(None);
+        };
+
+        let steps_since_reset = self.status()?.steps_since_reset;
+
+        let mut reported_exception = self.reported_exception.lock().unwrap();
+
+        match reported_exception.as_mut() {
+            Some((reported, last_stopped))
+                if *reported == report && steps_since_reset.wrapping_sub(*last_stopped) <= 1 =>
+            {
+                *last_stopped = steps_since_reset;
+
+                Ok(None)
+            }
+            _ => Ok(Some(report)),
+        }
+    }
+
+    /// Defines (or redefines) trap `trap_number` as a breakpoint on `memory_address`.
+    fn define_trap(
+        trap_number: u8,
+        memory_address: u32,
+        process: &mut Child,
+    ) -> Result<(), LibiguanaError> {
+        Self::define_masked_trap(trap_number, memory_address, u32::MAX, 0, 0, process)
+    }
+
+    /// Defines (or redefines) trap `trap_number` as a breakpoint on every instruction whose
+    /// address matches `address` in the bits set in `address_mask`, and whose encoding matches
+    /// `instruction` in the bits set in `instruction_mask`.
+    fn define_masked_trap(
+        trap_number: u8,
+        address: u32,
+        address_mask: u32,
+        instruction: u32,
+        instruction_mask: u32,
+        process: &mut Child,
+    ) -> Result<(), LibiguanaError> {
+        // Initial define trap command
+        ReaderWriter::write(&[0b0011_0000], process)?;
+
+        ReaderWriter::write(
+            &[
+                trap_number,
+                0b1111_1111, // Trap conditions (address and data both masked)
+                0b0000_1111, // Transfer size mask (all)
+            ],
+            process,
+        )?;
+
+        // Trap address A and B
+        ReaderWriter::write(&address.to_le_bytes(), process)?;
+        ReaderWriter::write(&address_mask.to_le_bytes(), process)?;
+
+        // Data A and B, each of which is two words, of which jimulator only looks at the first
+        ReaderWriter::write(&instruction.to_le_bytes(), process)?;
+        ReaderWriter::write(&[0; 4], process)?;
+        ReaderWriter::write(&instruction_mask.to_le_bytes(), process)?;
+        ReaderWriter::write(&[0; 4], process)?;
+
+        Ok(())
+    }
+
+    /// Defines (or redefines) watchpoint `watchpoint_number` on every read and write of any size
+    /// to the `size` bytes from `address`.
+    fn define_watchpoint(
+        watchpoint_number: u8,
+        address: u32,
+        size: u32,
+        process: &mut Child,
+    ) -> Result<(), LibiguanaError> {
+        // Initial define watchpoint command
+        ReaderWriter::write(&[0b0011_0100], process)?;
+
+        ReaderWriter::write(
+            &[
+                watchpoint_number,
+                0b0011_1011, // Conditions (reads, writes, address range and data masked)
+                0b0000_1111, // Transfer size mask (all)
+            ],
+            process,
+        )?;
+
+        // Address A and B, the first and last addresses of the range
+        ReaderWriter::write(&address.to_le_bytes(), process)?;
+        ReaderWriter::write(&(address + size - 1).to_le_bytes(), process)?;
+
+        // Data A and B, which match anything with a mask of 0
+        ReaderWriter::write(&[0; 16], process)?;
+
+        Ok(())
+    }
+
+    /// Clears watchpoint `watchpoint_number`'s defined bit, so it no longer stops the program.
+    fn clear_watchpoint(watchpoint_number: u8, process: &mut Child) -> Result<(), LibiguanaError> {
+        // Set watchpoint flags command
+        ReaderWriter::write(&[0b0011_0110], process)?;
+
+        // Send word A (all 0s) and word B (just this watchpoint)
+        ReaderWriter::write(&[0; 4], process)?;
+        ReaderWriter::write(&(1_u32 << watchpoint_number).to_le_bytes(), process)?;
+
+        Ok(())
+    }
+
+    /// Writes `peripheral`'s registers to memory.
+    fn write_peripheral_registers(&self, peripheral: &Peripheral) -> Result<(), LibiguanaError> {
+        let bytes = peripheral
+            .registers
+            .iter()
+            .flat_map(|register| register.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        self.write_memory(&bytes, peripheral.address)
+    }
+
+    /// Brings every peripheral's registers in memory up to date, undoing anything the program
+    /// wrote to read-only registers.
+    fn refresh_peripherals(&self) -> Result<(), LibiguanaError> {
+        let mut peripherals = self.peripherals.lock().unwrap();
+
+        if peripherals.is_empty() {
+            return Ok(());
+        }
+
+        let steps_since_reset = self.status()?.steps_since_reset;
+
+        for peripheral in peripherals.iter_mut() {
+            peripheral.refresh(steps_since_reset);
+            self.write_peripheral_registers(peripheral)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs `update` on the `kind` peripheral called `name` and writes its registers to memory.
+    fn update_peripheral(
+        &self,
+        name: &str,
+        kind: PeripheralKind,
+        update: impl FnOnce(&mut Peripheral),
+    ) -> Result<(), LibiguanaError> {
+        let mut peripherals = self.peripherals.lock().unwrap();
+
+        let peripheral = peripherals
+            .iter_mut()
+            .find(|peripheral| peripheral.name == name)
+            .ok_or_else(|| LibiguanaError::NoSuchPeripheral(name.to_string()))?;
+
+        if peripheral.kind != kind {
+            return Err(LibiguanaError::WrongPeripheralKind(name.to_string()));
+        }
+
+        update(peripheral);
+
+        peripheral.refresh(self.status()?.steps_since_reset);
+        self.write_peripheral_registers(peripheral)
+    }
+
+    /// Works out what the program did to the peripherals to stop on a watchpoint, as (index,
+    /// kind, offset, value). Writes show up as registers that no longer hold what was last
+    /// written to them. Otherwise, the program read a register, and which one is worked out from
+    /// the instruction that did it, or failing that, from the value it loaded.
+    fn peripheral_accesses(
+        &self,
+    ) -> Result<Vec<(usize, PeripheralAccessKind, u32, u32)>, LibiguanaError> {
+        let watched = self
+            .peripherals
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(_, peripheral)| peripheral.watchpoint_number.is_some())
+            .map(|(index, peripheral)| (index, peripheral.address, peripheral.registers.clone()))
+            .collect::<Vec<_>>();
+
+        let mut accesses = Vec::new();
+        let mut current_values = Vec::new();
+
+        for (index, address, registers) in &watched {
+            let bytes = self.read_memory_bytes(*address, registers.len() as u32 * 4)?;
+
+            for (register, bytes) in bytes.chunks_exact(4).enumerate() {
+                let value = u32::from_le_bytes(bytes.try_into()?);
+                let offset = register as u32 * 4;
+
+                if value != registers[register] {
+                    accesses.push((*index, PeripheralAccessKind::Write, offset, value));
+                }
+
+                current_values.push((*index, *address, offset, value));
+            }
+        }
+
+        if !accesses.is_empty() {
+            return Ok(accesses);
+        }
+
+        let registers = self.registers()?.as_array();
+        let is_thumb = self.cpsr()? & CPSR_THUMB_BIT != 0;
+
+        // The PC has moved on to the next instruction
+        let instruction_address =
+            registers[PC_REGISTER as usize].wrapping_sub(if is_thumb { 2 } else { 4 });
+
+        let bytes: [u8; 4] = self
+            .read_memory_bytes(instruction_address, 4)?
+            .as_slice()
+            .try_into()?;
+        let word = u32::from_le_bytes(bytes);
+
+        let instruction = if is_thumb {
+            arm_decoder::decode_instruction_details_thumb(word, instruction_address)?
+        } else {
+            arm_decoder::decode_instruction_details(word, instruction_address)?
+        };
+
+        // A store that left a register as it was wrote the value it already held
+        let kind = match instruction.memory_access.map(|access| access.kind) {
+            Some(MemoryAccessKind::Store) => PeripheralAccessKind::Write,
+            _ => PeripheralAccessKind::Read,
+        };
+
+        let access =
+            match peripheral::accessed_address(&instruction, instruction_address, &registers) {
+                Some(accessed) => current_values
+                    .iter()
+                    .find(|(_, address, offset, _)| address + offset == accessed & !0b11),
+                None => {
+                    let loaded = instruction
+                        .operands
+                        .first()
+                        .and_then(|operand| match operand {
+                            DecodedOperand::Register { register } => {
+                                Some(registers[*register as usize])
+                            }
+                            _ => None,
+                        });
+
+                    current_values
+                        .iter()
+                        .find(|(_, _, _, value)| Some(*value) == loaded)
+                }
+            };
+
+        Ok(access
+            .map(|(index, _, offset, value)| (*index, kind, *offset, *value))
+            .into_iter()
+            .collect())
+    }
+
+    /// Clears trap `trap_number`'s defined bit, so it no longer stops the program.
+    fn clear_trap(trap_number: u8, process: &mut Child) -> Result<(), LibiguanaError> {
+        // Set trap flags command
+        ReaderWriter::write(&[0b0011_0010], process)?;
+
+        // Send word A (all 0s) and word B (just this trap)
+        ReaderWriter::write(&[0; 4], process)?;
+        ReaderWriter::write(&(1_u32 << trap_number).to_le_bytes(), process)?;
+
+        Ok(())
+    }
+
+    /// Reads the register at `address` in jimulator's register space. The bottom 5 bits of the
+    /// address are the register number (16 is the CPSR and 17 is the SPSR), and the top 3 bits
+    /// select the bank, with 0 being the current mode's bank.
+    fn read_register_raw(address: u32, process: &mut Child) -> Result<u32, LibiguanaError> {
+        // Write memory transfer command: 01 (memory transfer), 01 (reg space), 1 (read), 010 (32
+        // bit)
+        ReaderWriter::write(&[0b0101_1010], process)?;
+
+        ReaderWriter::write(&address.to_le_bytes(), process)?;
+        ReaderWriter::write(&1_u16.to_le_bytes(), process)?;
+
+        let mut buf = [0; 4];
+        ReaderWriter::read_exact(&mut buf, process)?;
+
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    /// Writes `value` to the register at `address` in jimulator's register space. See
+    /// [`Self::read_register_raw`] for how addresses work.
+    fn write_register_raw(
+        address: u32,
+        value: u32,
+        process: &mut Child,
+    ) -> Result<(), LibiguanaError> {
+        // Write memory transfer command: 01 (memory transfer), 01 (reg space), 0 (write), 010 (32
+        // bit)
+        ReaderWriter::write(&[0b0101_0010], process)?;
+
+        ReaderWriter::write(&address.to_le_bytes(), process)?;
+        ReaderWriter::write(&1_u16.to_le_bytes(), process)?;
+        ReaderWriter::write(&value.to_le_bytes(), process)?;
+
+        Ok(())
+    }
+}
+
 impl Drop for IguanaEnvironment {
     fn drop(&mut self) {
         println!("Drop called!");
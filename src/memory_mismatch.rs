@@ -0,0 +1,16 @@
+/// A line of the loaded `.kmd` file whose bytes do not match what is currently in emulator memory.
+#[derive(Debug, PartialEq, Eq, uniffi::Record)]
+pub struct MemoryMismatch {
+    /// The address of the first byte of the line
+    pub memory_address: u32,
+
+    /// The bytes the `.kmd` file says should be at `memory_address`, up to where a later line
+    /// takes over
+    pub expected: Vec<u8>,
+
+    /// The bytes that are actually at `memory_address`
+    pub actual: Vec<u8>,
+
+    /// Whether the line is an instruction (as opposed to data)
+    pub is_instruction: bool,
+}
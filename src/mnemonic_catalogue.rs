@@ -0,0 +1,534 @@
+use std::collections::BTreeMap;
+
+use crate::{condition_code::ConditionCode, error::LibiguanaError};
+
+/// Which of aasm's tables a mnemonic is defined in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum MnemonicSet {
+    Directive,
+    Arm,
+    Thumb,
+}
+
+/// The condition suffixes aasm accepts, in the order of their encodings
+const CONDITIONS: [(&[&str], ConditionCode); 15] = [
+    (&["eq"], ConditionCode::Eq),
+    (&["ne"], ConditionCode::Ne),
+    (&["cs", "hs"], ConditionCode::Cs),
+    (&["cc", "lo"], ConditionCode::Cc),
+    (&["mi"], ConditionCode::Mi),
+    (&["pl"], ConditionCode::Pl),
+    (&["vs"], ConditionCode::Vs),
+    (&["vc"], ConditionCode::Vc),
+    (&["hi"], ConditionCode::Hi),
+    (&["ls"], ConditionCode::Ls),
+    (&["ge"], ConditionCode::Ge),
+    (&["lt"], ConditionCode::Lt),
+    (&["gt"], ConditionCode::Gt),
+    (&["le"], ConditionCode::Le),
+    (&["al"], ConditionCode::Al),
+];
+
+/// What a directive does, as decided by its encoding in the mnemonics file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum DirectiveKind {
+    Equ,
+    Org,
+    Align,
+    Def,
+    Record,
+    RecordAlign,
+
+    /// The field sizes used inside a `RECORD`/`STRUCT`
+    Alias,
+    Byte,
+    Halfword,
+    Word,
+    Doubleword,
+
+    Rn,
+    Cn,
+    Cp,
+    Defb,
+    Defh,
+    Defw,
+    Defs,
+    Export,
+    Include,
+    Literal,
+    Arch,
+    Entry,
+    Arm,
+    Thumb,
+    Set,
+    If,
+    Endif,
+    Else,
+    Import,
+
+    /// A directive this version of libiguana doesn't know about
+    Unknown,
+}
+
+impl From<u32> for DirectiveKind {
+    fn from(encoding: u32) -> Self {
+        match encoding & 0xFFFF_0000 {
+            0xF800_0000 => Self::Equ,
+            0xF801_0000 => Self::Org,
+            0xF802_0000 => Self::Align,
+            0xF803_0000 => Self::Def,
+            0xF804_0000 => Self::Record,
+            0xF805_0000 => Self::RecordAlign,
+            0xF810_0000 => Self::Alias,
+            0xF811_0000 => Self::Byte,
+            0xF812_0000 => Self::Halfword,
+            0xF814_0000 => Self::Word,
+            0xF818_0000 => Self::Doubleword,
+            0xF400_0000 => Self::Rn,
+            0xF401_0000 => Self::Cn,
+            0xF402_0000 => Self::Cp,
+            0xF000_0000 => Self::Defb,
+            0xF001_0000 => Self::Defh,
+            0xF002_0000 => Self::Defw,
+            0xF003_0000 => Self::Defs,
+            0xF004_0000 => Self::Export,
+            0xF005_0000 => Self::Include,
+            0xF006_0000 => Self::Literal,
+            0xF007_0000 => Self::Arch,
+            0xF008_0000 => Self::Entry,
+            0xF009_0000 => Self::Arm,
+            0xF00A_0000 => Self::Thumb,
+            0xF00B_0000 => Self::Set,
+            0xF00C_0000 => Self::If,
+            0xF00D_0000 => Self::Endif,
+            0xF00E_0000 => Self::Else,
+            0xF00F_0000 => Self::Import,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// A mnemonic aasm will accept, e.g. `ldmeqfd`.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct Mnemonic {
+    /// The full mnemonic, in lower case
+    pub name: String,
+
+    /// The mnemonic as written in the mnemonics file, before any condition or suffix was added
+    pub root: String,
+
+    pub set: MnemonicSet,
+
+    /// The token aasm assembles this mnemonic with. For ARM mnemonics, this includes the condition
+    /// and any suffix bits.
+    pub encoding: u32,
+
+    /// The condition, if one is written as part of the mnemonic
+    pub condition: Option<ConditionCode>,
+
+    /// `None` unless `set` is `Directive`
+    pub directive: Option<DirectiveKind>,
+
+    /// Whether the mnemonic may assemble to more than one instruction (e.g. `ADRL`)
+    pub is_variable_length: bool,
+}
+
+/// A line of the mnemonics file
+struct Root {
+    name: String,
+    set: MnemonicSet,
+    value: u32,
+}
+
+/// Every mnemonic and directive listed in a mnemonics file, expanded the same way aasm expands
+/// them.
+#[derive(uniffi::Object)]
+pub struct MnemonicCatalogue {
+    /// Keyed by the lower case name. Some names (e.g. `ldr`) have more than one entry. Each entry
+    /// is paired with the index of the root it came from.
+    mnemonics: BTreeMap<String, Vec<(usize, Mnemonic)>>,
+
+    roots: Vec<Root>,
+}
+
+#[uniffi::export]
+impl MnemonicCatalogue {
+    /// Parses the contents of a mnemonics file. Fails with the (1-based) line number of the first
+    /// line aasm wouldn't accept.
+    #[uniffi::constructor]
+    pub fn new(mnemonics_file: &str) -> Result<Self, LibiguanaError> {
+        let mut catalogue = Self {
+            mnemonics: BTreeMap::new(),
+            roots: Vec::new(),
+        };
+
+        for (index, line) in mnemonics_file.lines().enumerate() {
+            let Some(root) =
+                parse_line(line).ok_or(LibiguanaError::MnemonicsParseError(index as u32 + 1))?
+            else {
+                continue;
+            };
+
+            catalogue.add_root(catalogue.roots.len(), &root);
+            catalogue.roots.push(root);
+        }
+
+        Ok(catalogue)
+    }
+
+    /// Every definition of `name` (ignoring case). This is empty if aasm wouldn't know the name.
+    pub fn lookup(&self, name: &str) -> Vec<Mnemonic> {
+        self.mnemonics
+            .get(&name.to_lowercase())
+            .map(|definitions| {
+                definitions
+                    .iter()
+                    .map(|(_, mnemonic)| mnemonic.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether aasm would recognise `name` (ignoring case) as a mnemonic or directive.
+    pub fn is_known(&self, name: &str) -> bool {
+        self.mnemonics.contains_key(&name.to_lowercase())
+    }
+
+    /// The names that start with `prefix` (ignoring case), in alphabetical order. If `set` is given,
+    /// only names defined in that set are returned.
+    pub fn completions(&self, prefix: &str, set: Option<MnemonicSet>) -> Vec<String> {
+        let prefix = prefix.to_lowercase();
+
+        self.mnemonics
+            .range(prefix.clone()..)
+            .take_while(|(name, _)| name.starts_with(&prefix))
+            .filter(|(_, mnemonics)| match set {
+                Some(set) => mnemonics.iter().any(|(_, mnemonic)| mnemonic.set == set),
+                None => true,
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The other names in the mnemonics file that have the same encoding as `name`, e.g. `svc` for
+    /// `swi`. Conditions and suffixes aren't included, so `swieq` has the same aliases as `swi`.
+    pub fn aliases(&self, name: &str) -> Vec<String> {
+        let mut aliases = Vec::new();
+
+        let Some(definitions) = self.mnemonics.get(&name.to_lowercase()) else {
+            return aliases;
+        };
+
+        for (root_index, _) in definitions {
+            let definition_root = &self.roots[*root_index];
+
+            for root in &self.roots {
+                if root.set == definition_root.set
+                    && root.value == definition_root.value
+                    && root.name != definition_root.name
+                    && !aliases.contains(&root.name)
+                {
+                    aliases.push(root.name.clone());
+                }
+            }
+        }
+
+        aliases
+    }
+}
+
+impl MnemonicCatalogue {
+    fn add_root(&mut self, root_index: usize, root: &Root) {
+        let is_variable_length = root.value & 0x0000_8000 != 0;
+
+        let mut add = |name: String, encoding: u32, condition: Option<ConditionCode>| {
+            let mnemonic = Mnemonic {
+                name: name.clone(),
+                root: root.name.clone(),
+                set: root.set,
+                encoding,
+                condition,
+                directive: (root.set == MnemonicSet::Directive)
+                    .then(|| DirectiveKind::from(root.value)),
+                is_variable_length,
+            };
+
+            self.mnemonics
+                .entry(name)
+                .or_default()
+                .push((root_index, mnemonic));
+        };
+
+        if root.set != MnemonicSet::Arm {
+            add(root.name.clone(), root.value, None);
+            return;
+        }
+
+        let token = root.value & 0x0FFF_FFFF;
+        let variation = (root.value >> 16) & 0xF;
+
+        for (name, encoding) in arm_variants(&root.name, 0xE000_0000 | token, variation) {
+            add(name, encoding, None);
+        }
+
+        // <30> means the instruction can take any condition
+        if root.value & 0x4000_0000 == 0 {
+            return;
+        }
+
+        for (condition_number, (suffixes, condition)) in CONDITIONS.iter().enumerate() {
+            for suffix in suffixes.iter() {
+                let conditional_name = format!("{}{suffix}", root.name);
+                let conditional_token = ((condition_number as u32) << 28) | token;
+
+                for (name, encoding) in
+                    arm_variants(&conditional_name, conditional_token, variation)
+                {
+                    add(name, encoding, Some(*condition));
+                }
+            }
+        }
+    }
+}
+
+/// Parses a line like `add 40817000 ; <27-20> op code`. Returns `Some(None)` for blank lines
+/// and comments, and `None` if the line is malformed.
+fn parse_line(line: &str) -> Option<Option<Root>> {
+    let line = line.split(';').next().unwrap_or_default();
+    let mut fields = line.split_whitespace();
+
+    let Some(name) = fields.next() else {
+        return Some(None);
+    };
+
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    let value = u32::from_str_radix(&fields.next()?.replace('_', ""), 16).ok()?;
+
+    let set = if value & 0xF000_0000 == 0xF000_0000 {
+        MnemonicSet::Directive
+    } else if value & 0x0000_0100 != 0 {
+        MnemonicSet::Thumb
+    } else {
+        MnemonicSet::Arm
+    };
+
+    Some(Some(Root {
+        name: name.to_lowercase(),
+        set,
+        value,
+    }))
+}
+
+/// The mnemonics aasm defines for one ARM root, with the suffixes (`S`, `B`, `T`, LDM/STM modes
+/// and so on) its category allows. This mirrors `parse_mnem_variant` in aasm.
+fn arm_variants(name: &str, token: u32, variation: u32) -> Vec<(String, u32)> {
+    let with_suffix = |suffix: &str, mask: u32| (format!("{name}{suffix}"), token | mask);
+    let plain = (name.to_string(), token);
+
+    match variation {
+        // Arithmetic
+        0x1 | 0xD => vec![plain, with_suffix("s", 0x0010_0000)],
+
+        // Multiplies and others that just take registers
+        0x2 => match token & 0x0000_F000 {
+            0x1000 | 0x4000 => vec![plain, with_suffix("s", 0x0010_0000)],
+            0x2000 => vec![plain, with_suffix("b", 0x0040_0000)],
+            _ => vec![plain],
+        },
+
+        // LDR/STR
+        0x3 => vec![
+            plain,
+            with_suffix("t", 0x0000_1000),
+            with_suffix("b", 0x0040_0000),
+            with_suffix("bt", 0x0040_1000),
+        ],
+
+        // LDM/STM
+        0x6 => {
+            let modes: [&[&str]; 4] = if token & 0x0010_0000 == 0 {
+                [&["da", "ed"], &["ia", "ea"], &["db", "fd"], &["ib", "fa"]]
+            } else {
+                [&["da", "fa"], &["ia", "fd"], &["db", "ea"], &["ib", "ed"]]
+            };
+
+            modes
+                .iter()
+                .enumerate()
+                .flat_map(|(mode, suffixes)| {
+                    suffixes.iter().map(move |suffix| {
+                        (format!("{name}{suffix}"), token | ((mode as u32) << 23))
+                    })
+                })
+                .collect()
+        }
+
+        // LDRH etc.
+        0x9 => {
+            let mut variants = vec![with_suffix("h", 0)];
+
+            if token & 0x0010_0000 != 0 {
+                variants.push(with_suffix("sb", 0x0000_1000));
+                variants.push(with_suffix("sh", 0x0000_2000));
+            }
+
+            variants
+        }
+
+        // LDC/STC
+        0xB => vec![plain, with_suffix("l", 0x0040_0000)],
+
+        // LDRD/STRD
+        0xC => vec![with_suffix("d", 0)],
+
+        _ => vec![plain],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines from aasm's mnemonics file
+    const MNEMONICS: &str = "; Directives
+equ\tF8000000\t\t; <31-28> = F is directive flag
+org\tF8010000\t\t; <27> indicates can redefine label
+defw\tF0020000
+add\t40817000\t\t; <11>    Fxxxxxxx `always' instruction (except RRX)
+str\t44030000
+ldr\t44138000
+swi\t4F050000\t\t; Miscellaneous
+svc\t4F050000
+stm\t48060000
+ldm\t48160000
+adrl\t42088000\t\t; Variable length version
+str\t40090002\t\t; Half word load/store
+ldr\t40198002
+add\t00020100
+swi\t0DF70100
+";
+
+    fn catalogue() -> MnemonicCatalogue {
+        MnemonicCatalogue::new(MNEMONICS).unwrap()
+    }
+
+    fn encodings(mnemonics: Vec<Mnemonic>) -> Vec<(MnemonicSet, u32)> {
+        mnemonics
+            .into_iter()
+            .map(|mnemonic| (mnemonic.set, mnemonic.encoding))
+            .collect()
+    }
+
+    #[test]
+    fn looks_up_every_set_a_name_is_in() {
+        assert_eq!(
+            encodings(catalogue().lookup("ADD")),
+            vec![
+                (MnemonicSet::Arm, 0xE081_7000),
+                (MnemonicSet::Thumb, 0x0002_0100)
+            ]
+        );
+    }
+
+    #[test]
+    fn adds_conditions_and_the_s_suffix() {
+        let mnemonics = catalogue().lookup("addeqs");
+
+        assert_eq!(mnemonics.len(), 1);
+        assert_eq!(mnemonics[0].root, "add");
+        assert_eq!(mnemonics[0].condition, Some(ConditionCode::Eq));
+        assert_eq!(mnemonics[0].encoding, 0x0091_7000);
+
+        assert_eq!(
+            encodings(catalogue().lookup("addhs")),
+            encodings(catalogue().lookup("addcs"))
+        );
+    }
+
+    #[test]
+    fn expands_byte_and_translated_loads_and_stores() {
+        let catalogue = catalogue();
+
+        assert_eq!(catalogue.lookup("ldrb")[0].encoding, 0xE453_8000);
+        assert_eq!(catalogue.lookup("ldrt")[0].encoding, 0xE413_9000);
+        assert_eq!(catalogue.lookup("ldrnebt")[0].encoding, 0x1453_9000);
+        assert!(catalogue.lookup("ldr")[0].is_variable_length);
+    }
+
+    #[test]
+    fn only_loads_get_signed_halfwords() {
+        let catalogue = catalogue();
+
+        assert!(catalogue.is_known("ldrh"));
+        assert!(catalogue.is_known("ldrsb"));
+        assert!(catalogue.is_known("LDRSH"));
+        assert!(catalogue.is_known("strh"));
+        assert!(!catalogue.is_known("strsb"));
+        assert!(!catalogue.is_known("strsh"));
+    }
+
+    #[test]
+    fn block_transfer_modes_depend_on_the_direction() {
+        let catalogue = catalogue();
+
+        // The stack modes are the other way around for loads and stores
+        assert_eq!(catalogue.lookup("ldmfd")[0].encoding, 0xE896_0000);
+        assert_eq!(catalogue.lookup("ldmia")[0].encoding, 0xE896_0000);
+        assert_eq!(catalogue.lookup("stmfd")[0].encoding, 0xE906_0000);
+        assert_eq!(catalogue.lookup("stmdb")[0].encoding, 0xE906_0000);
+
+        assert_eq!(
+            catalogue.lookup("ldmeqfd")[0].condition,
+            Some(ConditionCode::Eq)
+        );
+        assert!(!catalogue.is_known("ldm"));
+        assert!(!catalogue.is_known("ldmfdeq"));
+    }
+
+    #[test]
+    fn directives() {
+        let mnemonics = catalogue().lookup("ORG");
+
+        assert_eq!(mnemonics[0].set, MnemonicSet::Directive);
+        assert_eq!(mnemonics[0].directive, Some(DirectiveKind::Org));
+        assert!(catalogue().lookup("adrl")[0].is_variable_length);
+    }
+
+    #[test]
+    fn completions() {
+        let catalogue = catalogue();
+
+        assert_eq!(catalogue.completions("SWIE", None), vec!["swieq"]);
+        assert_eq!(
+            catalogue.completions("swi", Some(MnemonicSet::Thumb)),
+            vec!["swi"]
+        );
+        assert_eq!(
+            catalogue.completions("", Some(MnemonicSet::Directive)),
+            vec!["defw", "equ", "org"]
+        );
+        assert!(catalogue.completions("x", None).is_empty());
+    }
+
+    #[test]
+    fn aliases_ignore_conditions() {
+        let catalogue = catalogue();
+
+        assert_eq!(catalogue.aliases("swi"), vec!["svc"]);
+        assert_eq!(catalogue.aliases("SWIEQ"), vec!["svc"]);
+        assert_eq!(catalogue.aliases("svc"), vec!["swi"]);
+        assert!(catalogue.aliases("add").is_empty());
+        assert!(catalogue.aliases("nothing").is_empty());
+    }
+
+    #[test]
+    fn reports_the_first_malformed_line() {
+        assert!(matches!(
+            MnemonicCatalogue::new("add\t40817000\nmov\tnot_hex\n"),
+            Err(LibiguanaError::MnemonicsParseError(2))
+        ));
+    }
+}
@@ -0,0 +1,389 @@
+//! Host-side devices mapped into the program's memory. Each one is a block of word registers:
+//!
+//! | Kind           | Offset | Register                                                        |
+//! |----------------|--------|-----------------------------------------------------------------|
+//! | `Leds`         | 0      | One bit per LED                                                 |
+//! | `Buttons`      | 0      | One bit per button, set while it is pressed (read only)         |
+//! | `Timer`        | 0      | Steps since reset (read only)                                   |
+//! |                | 4      | Raise an IRQ every this many steps (0 for never)                |
+//! | `Uart`         | 0      | The next received byte, with bit 8 set, or 0 if there isn't one |
+//! |                | 4      | Write a byte here to transmit it                                |
+//! | `SevenSegment` | 0      | One byte of segments (a-g, then the point) per digit            |
+
+use std::{collections::VecDeque, sync::Arc};
+
+use crate::decoded_instruction::{
+    DecodedInstruction, DecodedOperand, MemoryAccessKind, MemoryOffset, ShiftAmount, ShiftKind,
+};
+
+/// The offset of the timer register that sets the interrupt period
+pub const TIMER_PERIOD_OFFSET: u32 = 4;
+
+/// The bit set in a UART's receive register when it holds a byte
+const UART_RECEIVED_BIT: u32 = 0x100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum PeripheralKind {
+    Leds,
+    Buttons,
+    Timer,
+    Uart,
+    SevenSegment,
+}
+
+impl PeripheralKind {
+    /// The number of word registers the peripheral has
+    pub fn register_count(self) -> u32 {
+        match self {
+            Self::Timer | Self::Uart => 2,
+            Self::Leds | Self::Buttons | Self::SevenSegment => 1,
+        }
+    }
+
+    /// Whether the program's accesses have to be watched. Buttons are only ever changed by the
+    /// host, so don't need a watchpoint.
+    pub fn is_watched(self) -> bool {
+        self != Self::Buttons
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum PeripheralAccessKind {
+    Read,
+    Write,
+}
+
+/// A load or store the program made to one of a peripheral's registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct PeripheralAccess {
+    pub kind: PeripheralAccessKind,
+
+    /// The offset of the register from the peripheral's address
+    pub offset: u32,
+
+    /// The value that was read or written
+    pub value: u32,
+}
+
+/// Told about each access the program makes to a peripheral, after its effects have been
+/// applied.
+#[uniffi::export(callback_interface)]
+pub trait PeripheralObserver: Send + Sync {
+    fn peripheral_accessed(&self, name: String, access: PeripheralAccess);
+}
+
+/// Lets Rust code register a closure as an observer.
+impl<F> PeripheralObserver for F
+where
+    F: Fn(String, PeripheralAccess) + Send + Sync,
+{
+    fn peripheral_accessed(&self, name: String, access: PeripheralAccess) {
+        self(name, access)
+    }
+}
+
+pub struct Peripheral {
+    pub name: String,
+    pub kind: PeripheralKind,
+    pub address: u32,
+    pub observer: Arc<dyn PeripheralObserver>,
+
+    /// The watchpoint on the peripheral's registers, if it needs one
+    pub watchpoint_number: Option<u8>,
+
+    /// What the library last wrote to the registers, so that the program's writes can be spotted
+    pub registers: Vec<u32>,
+
+    /// Bytes sent to a UART that the program hasn't read yet
+    pub received: VecDeque<u8>,
+}
+
+impl Peripheral {
+    pub fn new(
+        name: String,
+        kind: PeripheralKind,
+        address: u32,
+        observer: Arc<dyn PeripheralObserver>,
+        watchpoint_number: Option<u8>,
+    ) -> Self {
+        Self {
+            name,
+            kind,
+            address,
+            observer,
+            watchpoint_number,
+            registers: vec![0; kind.register_count() as usize],
+            received: VecDeque::new(),
+        }
+    }
+
+    /// The number of bytes the peripheral's registers take up
+    pub fn size(&self) -> u32 {
+        self.kind.register_count() * 4
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        address.wrapping_sub(self.address) < self.size()
+    }
+
+    /// Updates the registers that change by themselves, ready to be written to memory.
+    pub fn refresh(&mut self, steps_since_reset: u32) {
+        match self.kind {
+            PeripheralKind::Timer => self.registers[0] = steps_since_reset,
+            PeripheralKind::Uart => {
+                self.registers[0] = self
+                    .received
+                    .front()
+                    .map_or(0, |byte| UART_RECEIVED_BIT | *byte as u32);
+                self.registers[1] = 0;
+            }
+            PeripheralKind::Leds | PeripheralKind::Buttons | PeripheralKind::SevenSegment => {}
+        }
+    }
+
+    /// Applies the program writing `value` to the register at `offset`. Writes to read-only
+    /// registers are undone when the registers are next written to memory.
+    pub fn write(&mut self, offset: u32, value: u32) -> PeripheralAccess {
+        let register = (offset / 4) as usize;
+
+        match (self.kind, offset) {
+            (PeripheralKind::Leds | PeripheralKind::SevenSegment, _) => {
+                self.registers[register] = value
+            }
+            (PeripheralKind::Timer, TIMER_PERIOD_OFFSET) => self.registers[register] = value,
+            _ => {}
+        }
+
+        PeripheralAccess {
+            kind: PeripheralAccessKind::Write,
+            offset,
+            value,
+        }
+    }
+
+    /// Applies the program reading `value` from the register at `offset`.
+    pub fn read(&mut self, offset: u32, value: u32) -> PeripheralAccess {
+        if self.kind == PeripheralKind::Uart && offset == 0 {
+            self.received.pop_front();
+        }
+
+        PeripheralAccess {
+            kind: PeripheralAccessKind::Read,
+            offset,
+            value,
+        }
+    }
+}
+
+/// Works out the address a load or store at `instruction_address` accessed, from `registers`
+/// as they are after it ran. Gives `None` for block transfers, and when the registers the address
+/// came from have since been overwritten.
+pub fn accessed_address(
+    instruction: &DecodedInstruction,
+    instruction_address: u32,
+    registers: &[u32; 16],
+) -> Option<u32> {
+    let access = instruction.memory_access?;
+
+    let (base, offset, subtract, is_preindexed, writeback) =
+        instruction
+            .operands
+            .iter()
+            .find_map(|operand| match operand {
+                DecodedOperand::Memory {
+                    base,
+                    offset,
+                    subtract,
+                    is_preindexed,
+                    writeback,
+                } => Some((*base, offset, *subtract, *is_preindexed, *writeback)),
+                _ => None,
+            })?;
+
+    let loaded = match (access.kind, instruction.operands.first()) {
+        (
+            MemoryAccessKind::Load | MemoryAccessKind::Swap,
+            Some(DecodedOperand::Register { register }),
+        ) => Some(*register),
+        _ => None,
+    };
+
+    let register = |register: u8| (loaded != Some(register)).then(|| registers[register as usize]);
+
+    let offset = match offset {
+        MemoryOffset::None => 0,
+        MemoryOffset::Immediate { value } => *value,
+        MemoryOffset::Register { register: offset } => register(*offset)?,
+        MemoryOffset::ShiftedRegister {
+            register: offset,
+            shift,
+        } => match shift.amount {
+            ShiftAmount::Immediate { amount } => shifted(register(*offset)?, shift.kind, amount)?,
+            ShiftAmount::Register { .. } => return None,
+        },
+    };
+
+    let apply = |base: u32| match subtract {
+        true => base.wrapping_sub(offset),
+        false => base.wrapping_add(offset),
+    };
+
+    let unapply = |base: u32| match subtract {
+        true => base.wrapping_add(offset),
+        false => base.wrapping_sub(offset),
+    };
+
+    // The PC reads as 8 bytes ahead in ARM, and 4 bytes ahead (word aligned) in Thumb
+    let base_before = if base == 15 {
+        match instruction.length {
+            2 => instruction_address.wrapping_add(4) & !0b11,
+            _ => instruction_address.wrapping_add(8),
+        }
+    } else if writeback || !is_preindexed {
+        unapply(registers[base as usize])
+    } else {
+        register(base)?
+    };
+
+    Some(match is_preindexed {
+        true => apply(base_before),
+        false => base_before,
+    })
+}
+
+/// Applies an immediate shift the way the encoding means it, so a shift of 0 is 32 for `LSR` and
+/// `ASR`, and `RRX` for `ROR`. Gives `None` for `RRX`, which needs the carry flag from before the
+/// instruction ran.
+fn shifted(value: u32, kind: ShiftKind, amount: u8) -> Option<u32> {
+    let amount = amount as u32;
+
+    match (kind, amount) {
+        (ShiftKind::Lsl, _) => Some(value.checked_shl(amount).unwrap_or(0)),
+        (ShiftKind::Lsr, 0) => Some(0),
+        (ShiftKind::Lsr, _) => Some(value.checked_shr(amount).unwrap_or(0)),
+        (ShiftKind::Asr, 0) => Some(((value as i32) >> 31) as u32),
+        (ShiftKind::Asr, _) => Some(((value as i32) >> amount.min(31)) as u32),
+        (ShiftKind::Ror, 0) => None,
+        (ShiftKind::Ror, _) => Some(value.rotate_right(amount)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm_decoder;
+
+    /// Where the address of an ARM `instruction` at `address` comes out, with `set` registers
+    /// as they are after it ran (and the rest 0)
+    fn arm_address(instruction: u32, address: u32, set: &[(usize, u32)]) -> Option<u32> {
+        let decoded = arm_decoder::decode_instruction_details(instruction, address).unwrap();
+
+        accessed_address(&decoded, address, &registers(set))
+    }
+
+    /// The same, for a Thumb `instruction`
+    fn thumb_address(instruction: u32, address: u32, set: &[(usize, u32)]) -> Option<u32> {
+        let decoded = arm_decoder::decode_instruction_details_thumb(instruction, address).unwrap();
+
+        accessed_address(&decoded, address, &registers(set))
+    }
+
+    fn registers(set: &[(usize, u32)]) -> [u32; 16] {
+        let mut registers = [0; 16];
+
+        for (register, value) in set {
+            registers[*register] = *value;
+        }
+
+        registers
+    }
+
+    #[test]
+    fn preindexed_without_writeback_uses_the_base() {
+        // LDR r0, [r1, #4]
+        assert_eq!(
+            arm_address(0xE591_0004, 0x100, &[(1, 0x1000)]),
+            Some(0x1004)
+        );
+    }
+
+    #[test]
+    fn preindexed_with_writeback_uses_the_updated_base() {
+        // LDR r0, [r1, #4]!
+        assert_eq!(
+            arm_address(0xE5B1_0004, 0x100, &[(1, 0x1004)]),
+            Some(0x1004)
+        );
+    }
+
+    #[test]
+    fn postindexed_undoes_the_writeback() {
+        // LDR r0, [r1], #4
+        assert_eq!(
+            arm_address(0xE491_0004, 0x100, &[(1, 0x1004)]),
+            Some(0x1000)
+        );
+    }
+
+    #[test]
+    fn loading_over_the_base_loses_the_address() {
+        // LDR r1, [r1, #4]
+        assert_eq!(arm_address(0xE591_1004, 0x100, &[(1, 0x1000)]), None);
+    }
+
+    #[test]
+    fn subtracts_a_register_offset() {
+        // STR r0, [r1, -r2]
+        assert_eq!(
+            arm_address(0xE701_0002, 0x100, &[(1, 0x1000), (2, 0x10)]),
+            Some(0xFF0)
+        );
+    }
+
+    #[test]
+    fn arm_pc_reads_8_bytes_ahead() {
+        // LDR r0, [pc, #8]
+        assert_eq!(arm_address(0xE59F_0008, 0x100, &[]), Some(0x110));
+    }
+
+    #[test]
+    fn thumb_pc_reads_4_bytes_ahead_word_aligned() {
+        // LDR r0, [pc, #4]
+        assert_eq!(thumb_address(0x4801, 0x102, &[]), Some(0x108));
+    }
+
+    #[test]
+    fn thumb_immediate_offset() {
+        // LDR r0, [r1, #4]
+        assert_eq!(thumb_address(0x6848, 0x102, &[(1, 0x1000)]), Some(0x1004));
+    }
+
+    #[test]
+    fn shifted_register_offsets() {
+        let set = [(1, 0x1000), (2, 0x8000_0000)];
+
+        // LDR r0, [r1, r2, LSR #32]
+        assert_eq!(arm_address(0xE791_0022, 0x100, &set), Some(0x1000));
+
+        // LDR r0, [r1, r2, ASR #32]
+        assert_eq!(arm_address(0xE791_0042, 0x100, &set), Some(0xFFF));
+
+        // LDR r0, [r1, r2, RRX]
+        assert_eq!(arm_address(0xE791_0062, 0x100, &set), None);
+
+        // LDR r0, [r1, r2, LSL #2]
+        assert_eq!(
+            arm_address(0xE791_0102, 0x100, &[(1, 0x1000), (2, 0x10)]),
+            Some(0x1040)
+        );
+    }
+
+    #[test]
+    fn immediate_shifts_of_0() {
+        assert_eq!(shifted(0x8000_0001, ShiftKind::Lsl, 0), Some(0x8000_0001));
+        assert_eq!(shifted(0x8000_0001, ShiftKind::Lsr, 0), Some(0));
+        assert_eq!(shifted(0x8000_0001, ShiftKind::Asr, 0), Some(0xFFFF_FFFF));
+        assert_eq!(shifted(0x7FFF_FFFF, ShiftKind::Asr, 0), Some(0));
+        assert_eq!(shifted(0x8000_0001, ShiftKind::Ror, 0), None);
+    }
+}
@@ -17,3 +17,13 @@ pub struct Registers {
     pub r14: u32,
     pub pc: u32,
 }
+
+impl Registers {
+    /// The registers in order, so that they can be indexed by register number
+    pub fn as_array(&self) -> [u32; 16] {
+        [
+            self.r0, self.r1, self.r2, self.r3, self.r4, self.r5, self.r6, self.r7, self.r8,
+            self.r9, self.r10, self.r11, self.r12, self.r13, self.r14, self.pc,
+        ]
+    }
+}
@@ -0,0 +1,35 @@
+/// A label whose address changed between the old and new `.kmd` file.
+#[derive(Debug, PartialEq, Eq, uniffi::Record)]
+pub struct MovedSymbol {
+    pub name: String,
+    pub old_address: u32,
+    pub new_address: u32,
+}
+
+/// A breakpoint that was moved to follow its label to a new address.
+#[derive(Debug, PartialEq, Eq, uniffi::Record)]
+pub struct MovedBreakpoint {
+    pub old_address: u32,
+    pub new_address: u32,
+}
+
+/// A summary of what [`crate::IguanaEnvironment::reload_kmd`] changed.
+#[derive(Debug, uniffi::Record)]
+pub struct ReloadReport {
+    /// The number of bytes that differed and were rewritten
+    pub bytes_written: u32,
+
+    /// Labels that are in both files but at different addresses
+    pub moved_symbols: Vec<MovedSymbol>,
+
+    /// Breakpoints that were moved to stay on the same instruction relative to their label
+    pub moved_breakpoints: Vec<MovedBreakpoint>,
+
+    /// Breakpoints that could not be moved because their label no longer exists. These are left
+    /// where they were.
+    pub orphaned_breakpoints: Vec<u32>,
+
+    /// True if no code shifted, meaning the new program can be patched into a paused program and
+    /// continued from where it was. If this is false, the program should be reset.
+    pub is_hot_patch_safe: bool,
+}
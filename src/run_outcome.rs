@@ -0,0 +1,37 @@
+use crate::{exception_report::ExceptionReport, status::BoardState};
+
+/// Why [`crate::IguanaEnvironment::run_until_stopped`] returned.
+#[derive(Debug, uniffi::Enum)]
+pub enum RunOutcome {
+    /// The program hit a breakpoint, finished, faulted or ran out of steps (which it can do on a
+    /// peripheral access, in which case it stops on a watchpoint)
+    Stopped { state: BoardState },
+
+    /// The program raised an exception, and is stopped at its vector. Only returned while
+    /// exceptions are being reported (see
+    /// [`crate::IguanaEnvironment::set_exception_reporting`]).
+    Exception { report: ExceptionReport },
+
+    /// The program is still running, but can't get any further until a character is written to
+    /// terminal 0
+    AwaitingInput,
+
+    /// The program was still running when the timeout ran out. It is left running.
+    TimedOut,
+}
+
+impl RunOutcome {
+    /// Why the program couldn't get any further, to finish a sentence like "the program ..."
+    pub fn description(&self) -> String {
+        match self {
+            Self::Stopped { state } => format!("stopped ({:?})", state.status),
+            Self::Exception { report } => format!(
+                "raised {} exception at {:#010x}",
+                report.kind.description(),
+                report.address
+            ),
+            Self::AwaitingInput => String::from("is waiting for input"),
+            Self::TimedOut => String::from("timed out"),
+        }
+    }
+}
@@ -1,12 +1,13 @@
 use enum_utils::TryFromRepr;
 
-#[derive(Debug, TryFromRepr, PartialEq, Eq, uniffi::Enum)]
+#[derive(Clone, Copy, Debug, TryFromRepr, PartialEq, Eq, uniffi::Enum)]
 #[repr(u8)]
 pub enum Status {
     Normal = 0x00,
     Busy = 0x01,
     Stopped = 0x40,
     Breakpoint = 0x41,
+    Watchpoint = 0x42,
     Memfault = 0x43,
     Finished = 0x44,
     Running = 0x80,
@@ -15,6 +16,13 @@ pub enum Status {
     Broken = 0x30,
 }
 
+impl Status {
+    /// Whether jimulator is still executing the program
+    pub fn is_running(self) -> bool {
+        matches!(self, Self::Running | Self::RunningSwi | Self::Stepping)
+    }
+}
+
 #[derive(Debug, uniffi::Record)]
 pub struct BoardState {
     pub status: Status,
@@ -0,0 +1,84 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::registers::Registers;
+
+/// Where jimulator sends the SWIs it doesn't handle itself
+pub const SWI_VECTOR: u32 = 0x08;
+
+/// The bits that make an ARM instruction a SWI, whatever its condition
+const ARM_SWI_MASK: u32 = 0x0F00_0000;
+
+/// The bits that make a Thumb instruction a SWI
+const THUMB_SWI_MASK: u32 = 0xFFFF_FF00;
+const THUMB_SWI: u32 = 0xDF00;
+
+/// The condition code that marks an ARM instruction as unconditional rather than a SWI
+const ARM_NEVER_CONDITION: u32 = 0xF;
+
+/// Services a SWI that jimulator doesn't handle itself (anything above `SWI 4`).
+#[uniffi::export(callback_interface)]
+pub trait SwiHandler: Send + Sync {
+    /// Handles `SWI number`. `registers` are the caller's, as they will be when the SWI returns,
+    /// so the PC is the return address. Returns the registers to carry on with - everything but
+    /// the PC is written back.
+    fn handle_swi(&self, number: u32, registers: Registers) -> Registers;
+}
+
+/// Lets Rust code register a closure as a handler.
+impl<F> SwiHandler for F
+where
+    F: Fn(u32, Registers) -> Registers + Send + Sync,
+{
+    fn handle_swi(&self, number: u32, registers: Registers) -> Registers {
+        self(number, registers)
+    }
+}
+
+#[derive(Default)]
+pub struct SwiHandlers {
+    pub handlers: HashMap<u32, Arc<dyn SwiHandler>>,
+
+    /// The trap number of the trap on the SWI vector, while there are handlers
+    pub trap_number: Option<u8>,
+}
+
+/// Decodes `instruction` as a SWI that will be taken, returning its number. ARM SWIs whose
+/// condition fails under `cpsr` aren't taken, so give `None`.
+pub fn decode_swi(instruction: u32, is_thumb: bool, cpsr: u32) -> Option<u32> {
+    if is_thumb {
+        return (instruction & THUMB_SWI_MASK == THUMB_SWI).then_some(instruction & 0xFF);
+    }
+
+    let condition = instruction >> 28;
+
+    (instruction & ARM_SWI_MASK == ARM_SWI_MASK
+        && condition != ARM_NEVER_CONDITION
+        && condition_passes(condition, cpsr))
+    .then_some(instruction & 0x00FF_FFFF)
+}
+
+/// Whether an instruction with `condition` executes with the flags in `cpsr`.
+fn condition_passes(condition: u32, cpsr: u32) -> bool {
+    let n = cpsr & (1 << 31) != 0;
+    let z = cpsr & (1 << 30) != 0;
+    let c = cpsr & (1 << 29) != 0;
+    let v = cpsr & (1 << 28) != 0;
+
+    match condition {
+        0x0 => z,
+        0x1 => !z,
+        0x2 => c,
+        0x3 => !c,
+        0x4 => n,
+        0x5 => !n,
+        0x6 => v,
+        0x7 => !v,
+        0x8 => c && !z,
+        0x9 => !c || z,
+        0xA => n == v,
+        0xB => n != v,
+        0xC => !z && n == v,
+        0xD => z || n != v,
+        _ => true,
+    }
+}
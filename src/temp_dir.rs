@@ -0,0 +1,67 @@
+use std::{
+    collections::hash_map::RandomState,
+    env, fs,
+    hash::{BuildHasher, Hasher},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Used to give every `TempDir` made by this process a different name
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+/// How many names to try before giving up. Each one has a random part, so needing more than one
+/// means something else is creating directories with our names.
+const MAX_ATTEMPTS: u32 = 16;
+
+/// A uniquely named directory in the system's temp directory, which is deleted (along with
+/// everything in it) on `Drop`. aasm can only read source from and write output to files, so this
+/// is where they go.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates a new directory, never reusing one that is already there - the temp directory is
+    /// shared, so anything already at the path could belong to (or be a symlink planted by)
+    /// someone else.
+    pub fn new() -> io::Result<Self> {
+        let mut builder = fs::DirBuilder::new();
+
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+        let mut attempts = 0;
+
+        loop {
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+            // RandomState is seeded randomly, so this is different every time
+            let random = RandomState::new().build_hasher().finish();
+
+            let path =
+                env::temp_dir().join(format!("libiguana-{}-{id}-{random:016x}", process::id()));
+
+            match builder.create(&path) {
+                Ok(()) => return Ok(Self { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < MAX_ATTEMPTS => {
+                    attempts += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            eprintln!("Failed to remove temp dir {:?}: {e:?}", self.path);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    process::Child,
+    sync::{Arc, Mutex},
+};
+
+use crate::{reader_writer::ReaderWriter, LibiguanaError};
+
+/// The number of terminals jimulator connects up in `terminalTable`
+pub const TERMINAL_COUNT: usize = 2;
+
+/// The most bytes jimulator is asked for in one read
+const READ_CHUNK_SIZE: u8 = 32;
+
+/// The most bytes taken from jimulator in one `fill`. A terminal's ring buffer (`RING_BUF_SIZE`
+/// in jimulator.cpp) can't hold any more than this, so anything past it was written while the
+/// output was being read, and can wait for the next read.
+const MAX_FILL_SIZE: usize = 64;
+
+/// One of jimulator's terminal devices. jimulator's SWIs read from and write to terminal 0, so the
+/// program can only write to terminal 1 through a SWI set up with
+/// [`crate::IguanaEnvironment::register_terminal_swi`], and nothing in jimulator reads what the
+/// host writes to it, so it can't be written to.
+///
+/// Reading terminal 1 needs a jimulator whose `BR_FR_READ` counts the requested device's buffer,
+/// like the jimulator.cpp in this repository. Older ones count terminal 0's output whichever
+/// device is asked for, so terminal 1 reads back garbage while terminal 0 has output waiting.
+#[derive(uniffi::Object)]
+pub struct Terminal {
+    device: u8,
+
+    jimulator_process: Arc<Mutex<Child>>,
+
+    /// Output that has been taken from jimulator (by `pending_count`) but not returned by `read`
+    /// yet
+    buffer: Mutex<Vec<u8>>,
+}
+
+#[uniffi::export]
+impl Terminal {
+    pub fn device(&self) -> u8 {
+        self.device
+    }
+
+    /// Everything the program has written to the terminal since the last read.
+    pub fn read(&self) -> Result<Vec<u8>, LibiguanaError> {
+        let mut buffer = self.buffer.lock().unwrap();
+
+        self.fill(&mut buffer)?;
+
+        Ok(std::mem::take(&mut buffer))
+    }
+
+    /// Sends `input` to the terminal, for the program to read. Only terminal 0 has anything
+    /// reading its input, so writing to any other terminal is an error rather than lost input.
+    pub fn write(&self, input: &[u8]) -> Result<(), LibiguanaError> {
+        if self.device != 0 {
+            return Err(LibiguanaError::TerminalNotWritable(self.device));
+        }
+
+        let mut process = self.jimulator_process.lock().unwrap();
+
+        // jimulator only takes one byte as length, so we have to chunk the input into chunks of 256
+        let chunks = input.chunks(u8::MAX as usize);
+
+        for chunk in chunks {
+            ReaderWriter::write(&[0b0001_0010, self.device], &mut process)?;
+
+            // Casting here should be fine, a chunk can't be bigger than u8::MAX
+            ReaderWriter::write(&[chunk.len() as u8], &mut process)?;
+
+            ReaderWriter::write(chunk, &mut process)?;
+
+            // jimulator returns 0 after every write for some reason
+            ReaderWriter::read_exact(&mut [0], &mut process)?;
+        }
+
+        Ok(())
+    }
+
+    /// The number of bytes waiting to be read. jimulator can't say how much output it has without
+    /// handing it over, so the output is kept until the next `read`.
+    pub fn pending_count(&self) -> Result<u32, LibiguanaError> {
+        let mut buffer = self.buffer.lock().unwrap();
+
+        self.fill(&mut buffer)?;
+
+        Ok(buffer.len().try_into()?)
+    }
+}
+
+impl Terminal {
+    pub fn new(device: u8, jimulator_process: Arc<Mutex<Child>>) -> Self {
+        Self {
+            device,
+            jimulator_process,
+            buffer: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds `output` to what the program has written to the terminal, for output that doesn't go
+    /// through jimulator.
+    pub(crate) fn push_output(&self, output: &[u8]) {
+        self.buffer.lock().unwrap().extend_from_slice(output);
+    }
+
+    /// Moves jimulator's output for this terminal into `buffer`, up to `MAX_FILL_SIZE` bytes.
+    fn fill(&self, buffer: &mut Vec<u8>) -> Result<(), LibiguanaError> {
+        let mut process = self.jimulator_process.lock().unwrap();
+
+        let mut filled = 0;
+
+        while filled < MAX_FILL_SIZE {
+            ReaderWriter::write(&[0b0001_0011, self.device, READ_CHUNK_SIZE], &mut process)?;
+
+            let mut length = [0; 1];
+
+            ReaderWriter::read_exact(&mut length, &mut process)?;
+
+            if length[0] == 0 {
+                return Ok(());
+            }
+
+            let mut chunk = vec![0; length[0] as usize];
+
+            ReaderWriter::read_exact(&mut chunk, &mut process)?;
+
+            filled += chunk.len();
+            buffer.append(&mut chunk);
+        }
+
+        Ok(())
+    }
+}
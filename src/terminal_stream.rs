@@ -0,0 +1,408 @@
+use std::{
+    io::{self, Read, Write},
+    str,
+    sync::{Arc, Mutex},
+};
+
+use crate::{terminal::Terminal, LibiguanaError};
+
+const ESCAPE: char = '\x1B';
+const BELL: char = '\x07';
+const BACKSPACE: char = '\x08';
+
+/// Where the stream is in an ANSI escape sequence
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum EscapeState {
+    #[default]
+    None,
+
+    /// Just after an ESC
+    Escape,
+
+    /// In a control sequence (`ESC [`), which ends with a byte from `@` to `~`
+    ControlSequence,
+
+    /// In an operating system command (`ESC ]`), which ends with BEL or `ESC \`
+    OperatingSystemCommand,
+    OperatingSystemCommandEscape,
+}
+
+#[derive(Default)]
+struct StreamState {
+    /// The start of a UTF-8 character that was split across reads from jimulator
+    partial_character: Vec<u8>,
+
+    escape: EscapeState,
+
+    /// Whether the last character was a CR, which is either the start of a CRLF or a return to the
+    /// start of the line
+    after_carriage_return: bool,
+
+    /// Decoded text that hasn't been read yet. This is always valid UTF-8 unless a `Read` stopped
+    /// part way through a character.
+    text: Vec<u8>,
+}
+
+/// A terminal that hands out text rather than bytes. Characters split across jimulator's reads
+/// are held back until they are complete, and ANSI control codes can be interpreted rather than
+/// passed through.
+///
+/// The text methods and the `Read` implementation share the same buffer, so each piece of output
+/// is only returned once.
+#[derive(uniffi::Object)]
+pub struct TerminalStream {
+    terminal: Arc<Terminal>,
+
+    /// Whether to strip escape sequences and the bell, apply backspaces, turn CRLF into LF and treat
+    /// a lone CR as clearing the current line
+    interpret_ansi: bool,
+
+    state: Mutex<StreamState>,
+}
+
+#[uniffi::export]
+impl TerminalStream {
+    #[uniffi::constructor]
+    pub fn new(terminal: Arc<Terminal>, interpret_ansi: bool) -> Self {
+        Self {
+            terminal,
+            interpret_ansi,
+            state: Mutex::new(StreamState::default()),
+        }
+    }
+
+    /// All the complete characters written since the last read, including any unfinished line.
+    pub fn read_text(&self) -> Result<String, LibiguanaError> {
+        let mut state = self.state.lock().unwrap();
+
+        self.poll(&mut state)?;
+
+        let text = std::mem::take(&mut state.text);
+
+        Ok(String::from_utf8_lossy(&text).into_owned())
+    }
+
+    /// The next complete line, without its line ending, or `None` if a whole line hasn't been
+    /// written yet.
+    pub fn read_line(&self) -> Result<Option<String>, LibiguanaError> {
+        let mut state = self.state.lock().unwrap();
+
+        self.poll(&mut state)?;
+
+        let Some(end) = state.text.iter().position(|byte| *byte == b'\n') else {
+            return Ok(None);
+        };
+
+        let mut line = state.text.drain(..=end).collect::<Vec<_>>();
+        line.pop();
+
+        // CRLF is only turned into LF when ANSI codes are interpreted
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+    }
+
+    /// Every complete line that has been written, without their line endings. Any unfinished line
+    /// is left for later.
+    pub fn read_lines(&self) -> Result<Vec<String>, LibiguanaError> {
+        let mut lines = Vec::new();
+
+        while let Some(line) = self.read_line()? {
+            lines.push(line);
+        }
+
+        Ok(lines)
+    }
+
+    pub fn write_text(&self, text: &str) -> Result<(), LibiguanaError> {
+        self.terminal.write(text.as_bytes())
+    }
+
+    /// Writes `line` followed by a newline.
+    pub fn write_line(&self, line: &str) -> Result<(), LibiguanaError> {
+        self.terminal.write(format!("{line}\n").as_bytes())
+    }
+}
+
+impl TerminalStream {
+    /// Decodes whatever jimulator has for the terminal onto the end of `state.text`.
+    fn poll(&self, state: &mut StreamState) -> Result<(), LibiguanaError> {
+        decode(state, &self.terminal.read()?, self.interpret_ansi);
+
+        Ok(())
+    }
+}
+
+/// Decodes `output` onto the end of `state.text`, carrying on from wherever the last output left
+/// off.
+fn decode(state: &mut StreamState, output: &[u8], interpret_ansi: bool) {
+    let mut bytes = std::mem::take(&mut state.partial_character);
+    bytes.extend_from_slice(output);
+
+    let mut remaining = bytes.as_slice();
+
+    while !remaining.is_empty() {
+        let (valid, rest) = match str::from_utf8(remaining) {
+            Ok(valid) => (valid, &[][..]),
+            Err(error) => {
+                let (valid, rest) = remaining.split_at(error.valid_up_to());
+
+                // Valid up to `valid_up_to`, so this can't fail
+                let valid = str::from_utf8(valid).unwrap_or_default();
+
+                match error.error_len() {
+                    Some(length) => {
+                        push_text(state, valid, interpret_ansi);
+                        push_text(
+                            state,
+                            &char::REPLACEMENT_CHARACTER.to_string(),
+                            interpret_ansi,
+                        );
+
+                        remaining = &rest[length..];
+                        continue;
+                    }
+
+                    // The character carries on in the next read
+                    None => {
+                        state.partial_character = rest.to_vec();
+                        (valid, &[][..])
+                    }
+                }
+            }
+        };
+
+        push_text(state, valid, interpret_ansi);
+        remaining = rest;
+    }
+}
+
+fn push_text(state: &mut StreamState, text: &str, interpret_ansi: bool) {
+    if !interpret_ansi {
+        state.text.extend_from_slice(text.as_bytes());
+        return;
+    }
+
+    for character in text.chars() {
+        interpret(state, character);
+    }
+}
+
+/// Adds `character` to `state.text`, acting on it if it is a control code.
+fn interpret(state: &mut StreamState, character: char) {
+    match state.escape {
+        EscapeState::None => {}
+        EscapeState::Escape => {
+            state.escape = match character {
+                '[' => EscapeState::ControlSequence,
+                ']' => EscapeState::OperatingSystemCommand,
+                _ => EscapeState::None,
+            };
+
+            return;
+        }
+        EscapeState::ControlSequence => {
+            if ('@'..='~').contains(&character) {
+                state.escape = EscapeState::None;
+            }
+
+            return;
+        }
+        EscapeState::OperatingSystemCommand => {
+            match character {
+                BELL => state.escape = EscapeState::None,
+                ESCAPE => state.escape = EscapeState::OperatingSystemCommandEscape,
+                _ => {}
+            }
+
+            return;
+        }
+        EscapeState::OperatingSystemCommandEscape => {
+            state.escape = match character {
+                '\\' => EscapeState::None,
+                _ => EscapeState::OperatingSystemCommand,
+            };
+
+            return;
+        }
+    }
+
+    if std::mem::take(&mut state.after_carriage_return) && character != '\n' {
+        let line_start = current_line_start(&state.text);
+        state.text.truncate(line_start);
+    }
+
+    match character {
+        ESCAPE => state.escape = EscapeState::Escape,
+        BELL => {}
+        BACKSPACE => {
+            if current_line_start(&state.text) < state.text.len() {
+                pop_character(&mut state.text);
+            }
+        }
+        '\r' => state.after_carriage_return = true,
+        _ => {
+            let mut encoded = [0; 4];
+
+            state
+                .text
+                .extend_from_slice(character.encode_utf8(&mut encoded).as_bytes());
+        }
+    }
+}
+
+/// The index just after the last newline in `text`
+fn current_line_start(text: &[u8]) -> usize {
+    text.iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |newline| newline + 1)
+}
+
+/// Removes the last UTF-8 character from `text`.
+fn pop_character(text: &mut Vec<u8>) {
+    // Continuation bytes are 0b10xx_xxxx
+    while let Some(byte) = text.pop() {
+        if byte & 0b1100_0000 != 0b1000_0000 {
+            break;
+        }
+    }
+}
+
+/// Reads decoded text. Returns `WouldBlock` if the program hasn't written anything, since there is
+/// no end to a terminal's output.
+impl Read for &TerminalStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.text.is_empty() {
+            self.poll(&mut state).map_err(io::Error::other)?;
+        }
+
+        if state.text.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let length = buf.len().min(state.text.len());
+
+        for (destination, byte) in buf.iter_mut().zip(state.text.drain(..length)) {
+            *destination = byte;
+        }
+
+        Ok(length)
+    }
+}
+
+impl Read for TerminalStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for &TerminalStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.terminal.write(buf).map_err(io::Error::other)?;
+
+        Ok(buf.len())
+    }
+
+    /// Writes go straight to jimulator, so there is nothing to flush
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for TerminalStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes each of `reads` in turn, as if they were separate reads from jimulator
+    fn decoded(reads: &[&[u8]], interpret_ansi: bool) -> String {
+        let mut state = StreamState::default();
+
+        for read in reads {
+            decode(&mut state, read, interpret_ansi);
+        }
+
+        String::from_utf8(state.text).unwrap()
+    }
+
+    #[test]
+    fn holds_back_a_character_split_across_reads() {
+        let mut output = vec![b'a'; 31];
+        output.extend_from_slice("é".as_bytes());
+
+        let mut state = StreamState::default();
+
+        decode(&mut state, &output[..32], false);
+        assert_eq!(state.text, vec![b'a'; 31]);
+
+        decode(&mut state, &output[32..], false);
+        assert_eq!(
+            String::from_utf8(state.text).unwrap(),
+            format!("{}é", "a".repeat(31))
+        );
+    }
+
+    #[test]
+    fn holds_back_a_character_split_across_several_reads() {
+        let crab = "🦀".as_bytes();
+
+        assert_eq!(
+            decoded(&[b"x", &crab[..1], &crab[1..3], &crab[3..], b"y"], false),
+            "x🦀y"
+        );
+    }
+
+    #[test]
+    fn replaces_invalid_bytes() {
+        assert_eq!(decoded(&[b"a\xFFb"], false), "a\u{FFFD}b");
+        assert_eq!(decoded(&[b"a\xC3", b"b"], false), "a\u{FFFD}b");
+    }
+
+    #[test]
+    fn passes_control_codes_through_when_not_interpreting() {
+        assert_eq!(
+            decoded(&[b"\x1B[31mred\x1B[0m\r\n\x08"], false),
+            "\x1B[31mred\x1B[0m\r\n\x08"
+        );
+    }
+
+    #[test]
+    fn strips_escape_sequences() {
+        assert_eq!(decoded(&[b"\x1B[31mred\x1B[0m"], true), "red");
+        assert_eq!(decoded(&[b"\x1B]0;title\x07text"], true), "text");
+        assert_eq!(decoded(&[b"\x1B]0;title\x1B\\text"], true), "text");
+        assert_eq!(decoded(&[b"ding\x07"], true), "ding");
+    }
+
+    #[test]
+    fn strips_escape_sequences_split_across_reads() {
+        assert_eq!(decoded(&[b"\x1B", b"[3", b"1mred"], true), "red");
+    }
+
+    #[test]
+    fn handles_carriage_returns() {
+        assert_eq!(decoded(&[b"line\r\nnext"], true), "line\nnext");
+        assert_eq!(decoded(&[b"10%\r20%\r", b"\n"], true), "20%\n");
+        assert_eq!(decoded(&[b"done\nhello\rbye"], true), "done\nbye");
+    }
+
+    #[test]
+    fn applies_backspaces_within_the_line() {
+        assert_eq!(decoded(&[b"abc\x08\x08d"], true), "ad");
+        assert_eq!(decoded(&["é\x08e".as_bytes()], true), "e");
+        assert_eq!(decoded(&[b"a\n\x08b"], true), "a\nb");
+    }
+}
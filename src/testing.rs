@@ -0,0 +1,517 @@
+//! Helpers for unit testing assembly programs with `cargo test`.
+//!
+//! A [`ProgramTest`] loads a program into its own jimulator, sets up registers and memory, runs
+//! the program with a step budget, and then checks what it did. Everything panics on failure, the
+//! same as `assert_eq!`, with a message that shows where the program went wrong.
+//!
+//! ```no_run
+//! use libiguana::{testing::ProgramTest, Status};
+//!
+//! let mut test = ProgramTest::new("jimulator", "/usr/local/bin/aasm", "/usr/local/bin/mnemonics");
+//!
+//! test.load_source_file("submission.s");
+//!
+//! // Call one function, which returns to the test rather than carrying on with the program
+//! let result = test.call_function("multiply", &[6, 7]);
+//! assert_eq!(result.r0, 42);
+//!
+//! // Run the whole program, which ends with `SWI 2`
+//! test.run(1000);
+//!
+//! test.assert_status(Status::Finished);
+//! test.assert_output("42\n");
+//! ```
+
+use std::{fmt::Write, fs, sync::Arc, time::Duration};
+
+use crate::{
+    instruction_formatter::register_name, kmd_extensions::KmdExtensions,
+    terminal_stream::TerminalStream, CompileResult, FunctionReturn, IguanaEnvironment, RunOutcome,
+    Status,
+};
+
+/// The register that `call` points at a label
+const PC: u8 = 15;
+
+/// How many bytes `assert_memory` shows on each line
+const BYTES_PER_LINE: usize = 16;
+
+/// How long `run` waits for the step budget to run out, unless `set_timeout` says otherwise
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct ProgramTest {
+    environment: Arc<IguanaEnvironment>,
+    terminal: Arc<TerminalStream>,
+
+    /// Everything the program has written to terminal 0 since the test started
+    output: String,
+
+    timeout: Duration,
+}
+
+impl ProgramTest {
+    /// Starts a jimulator for the test. See [`IguanaEnvironment::new`] for the paths.
+    #[track_caller]
+    pub fn new(jimulator_path: &str, aasm_path: &str, mnemonics_path: &str) -> Self {
+        let environment = expect(
+            IguanaEnvironment::new(jimulator_path, aasm_path.into(), mnemonics_path.into()),
+            "set up the environment",
+        );
+
+        Self::from_environment(Arc::new(environment))
+    }
+
+    #[track_caller]
+    pub fn from_environment(environment: Arc<IguanaEnvironment>) -> Self {
+        let terminal = expect(environment.terminal_stream(0, false), "open terminal 0");
+
+        Self {
+            environment,
+            terminal,
+            output: String::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// The environment under the test, for anything the test doesn't cover
+    pub fn environment(&self) -> &IguanaEnvironment {
+        &self.environment
+    }
+
+    /// How long `run` waits for the program before giving up. Programs that are stuck in a loop
+    /// normally run out of steps well before this.
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Assembles and loads `source`, and resets the board so that the program starts from 0.
+    #[track_caller]
+    pub fn load_source(&mut self, source: &str) -> &mut Self {
+        let result = expect(
+            self.environment.assemble_and_load(source),
+            "assemble the source",
+        );
+
+        self.check_assembled(&result, "The source");
+        self.reset()
+    }
+
+    /// Assembles and loads the `.s` file at `path`, and resets the board.
+    #[track_caller]
+    pub fn load_source_file(&mut self, path: &str) -> &mut Self {
+        let result = expect(self.environment.compile_aasm(path), "assemble the file");
+
+        self.check_assembled(&result, path);
+
+        let kmd = &result.output().kmd;
+
+        expect(self.environment.load_kmd(kmd), "load the KMD");
+        self.reset()
+    }
+
+    /// Loads the contents of a `.kmd` file, and resets the board.
+    #[track_caller]
+    pub fn load_kmd(&mut self, kmd: &str) -> &mut Self {
+        expect(self.environment.load_kmd(kmd), "load the KMD");
+        self.reset()
+    }
+
+    #[track_caller]
+    pub fn load_kmd_file(&mut self, path: &str) -> &mut Self {
+        let kmd = match fs::read_to_string(path) {
+            Ok(kmd) => kmd,
+            Err(error) => panic!("Couldn't read {path}: {error}"),
+        };
+
+        self.load_kmd(&kmd)
+    }
+
+    #[track_caller]
+    pub fn set_register(&mut self, register: u8, value: u32) -> &mut Self {
+        expect(
+            self.environment.write_register(register, value),
+            &format!("set {}", register_name(register)),
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn set_memory(&mut self, address: u32, bytes: &[u8]) -> &mut Self {
+        expect(
+            self.environment.write_memory(bytes, address),
+            &format!("write memory at {address:#010X}"),
+        );
+        self
+    }
+
+    /// Writes `words` from `address` onwards, little endian.
+    #[track_caller]
+    pub fn set_words(&mut self, address: u32, words: &[u32]) -> &mut Self {
+        let bytes = words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        self.set_memory(address, &bytes)
+    }
+
+    /// Sends `text` to terminal 0, for the program to read with `SWI 1`.
+    #[track_caller]
+    pub fn write_input(&mut self, text: &str) -> &mut Self {
+        expect(
+            self.environment.write_to_terminal(text.as_bytes()),
+            "write to terminal 0",
+        );
+        self
+    }
+
+    /// Points the PC at `label`, so that the next `run` starts there.
+    #[track_caller]
+    pub fn call(&mut self, label: &str) -> &mut Self {
+        let address = self.label_address(label);
+
+        self.set_register(PC, address)
+    }
+
+    /// Calls the function at `label` with `arguments`, leaving the rest of the program's state as
+    /// it was. See [`IguanaEnvironment::call_function_at`].
+    #[track_caller]
+    pub fn call_function(&mut self, label: &str, arguments: &[u32]) -> FunctionReturn {
+        let timeout_ms = self.timeout.as_millis().try_into().unwrap_or(u32::MAX);
+
+        let result = expect(
+            self.environment
+                .call_function(label, arguments.to_vec(), timeout_ms),
+            &format!("call {label}"),
+        );
+
+        self.read_output();
+
+        result
+    }
+
+    /// The address of `label` in the loaded program
+    #[track_caller]
+    pub fn label_address(&self, label: &str) -> u32 {
+        let Some(kmd) = self.environment.current_kmd() else {
+            panic!("No program is loaded");
+        };
+
+        match kmd.label_named(label) {
+            Some(label) => label.memory_address,
+            None => panic!("The program has no label called {label:?}"),
+        }
+    }
+
+    /// Runs the program until it stops or has run `steps` steps. Panics if the program waits for
+    /// input that hasn't been written, raises an exception while they are being reported, or is
+    /// still running after the timeout.
+    #[track_caller]
+    pub fn run(&mut self, steps: u32) -> &mut Self {
+        let timeout_ms = self.timeout.as_millis().try_into().unwrap_or(u32::MAX);
+
+        let outcome = expect(
+            self.environment.run_until_stopped(steps, timeout_ms),
+            "run the program",
+        );
+
+        self.read_output();
+
+        match outcome {
+            RunOutcome::Stopped { .. } => self,
+            RunOutcome::Exception { ref report } => panic!(
+                "The program {} ({})\nOutput so far:\n{}",
+                outcome.description(),
+                report.source_line.as_deref().unwrap_or("no source line"),
+                self.output
+            ),
+            RunOutcome::AwaitingInput => {
+                let _ = self.environment.stop_execution();
+
+                panic!(
+                    "The program is waiting for input, but none was written\nOutput so far:\n{}",
+                    self.output
+                )
+            }
+            RunOutcome::TimedOut => {
+                let _ = self.environment.stop_execution();
+
+                panic!(
+                    "The program was still running after {:?}\nOutput so far:\n{}",
+                    self.timeout, self.output
+                )
+            }
+        }
+    }
+
+    /// Everything the program has written to terminal 0 since the program was loaded
+    pub fn output(&mut self) -> &str {
+        self.read_output();
+        &self.output
+    }
+
+    #[track_caller]
+    pub fn assert_status(&mut self, expected: Status) -> &mut Self {
+        let state = expect(self.environment.status(), "get the status");
+
+        assert!(
+            state.status == expected,
+            "Expected the program to be {expected:?}, but it is {:?} after {} steps",
+            state.status,
+            state.steps_since_reset
+        );
+
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_register(&mut self, register: u8, expected: u32) -> &mut Self {
+        self.assert_registers(&[(register, expected)])
+    }
+
+    /// Checks several registers at once, listing every one that is wrong.
+    #[track_caller]
+    pub fn assert_registers(&mut self, expected: &[(u8, u32)]) -> &mut Self {
+        let registers = expect(self.environment.registers(), "read the registers").as_array();
+
+        let mut mismatches = String::new();
+
+        for &(register, expected) in expected {
+            let Some(&actual) = registers.get(register as usize) else {
+                panic!("{register} is not a valid register number");
+            };
+
+            if actual != expected {
+                let _ = writeln!(
+                    mismatches,
+                    "  {:<3} expected {expected:#010X} ({}), was {actual:#010X} ({})",
+                    register_name(register),
+                    expected as i32,
+                    actual as i32,
+                );
+            }
+        }
+
+        assert!(
+            mismatches.is_empty(),
+            "Registers didn't match:\n{mismatches}"
+        );
+
+        self
+    }
+
+    /// Checks the bytes from `address` onwards. Failures show both versions as a hex dump, with
+    /// the bytes that differ marked.
+    #[track_caller]
+    pub fn assert_memory(&mut self, address: u32, expected: &[u8]) -> &mut Self {
+        let actual = expect(
+            self.environment
+                .read_memory_bytes(address, expected.len() as u32),
+            "read memory",
+        );
+
+        if actual != expected {
+            panic!(
+                "Memory at {address:#010X} didn't match:\n{}",
+                memory_diff(address, expected, &actual)
+            );
+        }
+
+        self
+    }
+
+    /// Checks the words from `address` onwards, little endian.
+    #[track_caller]
+    pub fn assert_words(&mut self, address: u32, expected: &[u32]) -> &mut Self {
+        let bytes = expected
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        self.assert_memory(address, &bytes)
+    }
+
+    /// Checks everything the program has written to terminal 0. Failures show the lines that
+    /// differ.
+    #[track_caller]
+    pub fn assert_output(&mut self, expected: &str) -> &mut Self {
+        self.read_output();
+
+        if self.output != expected {
+            panic!(
+                "Terminal output didn't match:\n{}",
+                output_diff(expected, &self.output)
+            );
+        }
+
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_output_contains(&mut self, expected: &str) -> &mut Self {
+        self.read_output();
+
+        assert!(
+            self.output.contains(expected),
+            "Expected the terminal output to contain {expected:?}, but it was:\n{}",
+            self.output
+        );
+
+        self
+    }
+}
+
+impl ProgramTest {
+    #[track_caller]
+    fn check_assembled(&self, result: &CompileResult, name: &str) {
+        if !result.is_success() {
+            panic!(
+                "{name} failed to assemble:\n{}",
+                result.output().aasm_terminal
+            );
+        }
+    }
+
+    /// Resets the board, and forgets the last program's output.
+    #[track_caller]
+    fn reset(&mut self) -> &mut Self {
+        expect(self.environment.reset(), "reset the board");
+
+        self.read_output();
+        self.output.clear();
+
+        self
+    }
+
+    #[track_caller]
+    fn read_output(&mut self) {
+        let output = expect(self.terminal.read_text(), "read terminal 0");
+
+        self.output.push_str(&output);
+    }
+}
+
+/// Unwraps `result`, panicking with what the test was trying to do if it failed. This is a
+/// `match` rather than `unwrap_or_else` so that the panic points at the test, not this file.
+#[track_caller]
+fn expect<T>(result: Result<T, crate::LibiguanaError>, action: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(error) => panic!("Couldn't {action}: {error}"),
+    }
+}
+
+/// Both versions of some memory as hex dumps, with `^^` under each byte that differs
+fn memory_diff(address: u32, expected: &[u8], actual: &[u8]) -> String {
+    let mut diff = String::new();
+
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    for (line, (expected, actual)) in expected
+        .chunks(BYTES_PER_LINE)
+        .zip(actual.chunks(BYTES_PER_LINE))
+        .enumerate()
+    {
+        if expected == actual {
+            continue;
+        }
+
+        let line_address = address.wrapping_add((line * BYTES_PER_LINE) as u32);
+
+        let markers = expected
+            .iter()
+            .zip(actual)
+            .map(|(expected, actual)| if expected == actual { "  " } else { "^^" })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let _ = writeln!(diff, "  {line_address:08X}  expected {}", hex(expected));
+        let _ = writeln!(diff, "            actual   {}", hex(actual));
+        let _ = writeln!(diff, "                     {}", markers.trim_end());
+    }
+
+    diff
+}
+
+/// The lines of the expected and actual output, with `-` for expected lines that are missing or
+/// different and `+` for what was written instead
+fn output_diff(expected: &str, actual: &str) -> String {
+    let expected = expected.split_inclusive('\n').collect::<Vec<_>>();
+    let actual = actual.split_inclusive('\n').collect::<Vec<_>>();
+
+    let mut diff = String::new();
+
+    for line in 0..expected.len().max(actual.len()) {
+        match (expected.get(line), actual.get(line)) {
+            (Some(expected), Some(actual)) if expected == actual => {
+                let _ = writeln!(diff, "  {expected:?}");
+            }
+            (expected, actual) => {
+                if let Some(expected) = expected {
+                    let _ = writeln!(diff, "- {expected:?}");
+                }
+
+                if let Some(actual) = actual {
+                    let _ = writeln!(diff, "+ {actual:?}");
+                }
+            }
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_diff_marks_the_bytes_that_differ() {
+        assert_eq!(
+            memory_diff(0x100, &[1, 2, 3], &[1, 9, 3]),
+            "  00000100  expected 01 02 03\n            actual   01 09 03\n                        ^^\n"
+        );
+    }
+
+    #[test]
+    fn memory_diff_only_shows_lines_that_differ() {
+        let expected = [0; 32];
+        let mut actual = [0; 32];
+        actual[17] = 0xFF;
+
+        let diff = memory_diff(0x1000, &expected, &actual);
+
+        assert!(!diff.contains("00001000"));
+        assert!(diff.starts_with("  00001010  expected 00 00"));
+        assert!(diff.contains("actual   00 FF 00"));
+        assert_eq!(diff.lines().count(), 3);
+    }
+
+    #[test]
+    fn memory_diff_is_empty_when_nothing_differs() {
+        assert_eq!(memory_diff(0, &[1, 2, 3], &[1, 2, 3]), "");
+    }
+
+    #[test]
+    fn output_diff_marks_changed_and_extra_lines() {
+        assert_eq!(
+            output_diff("a\nb\n", "a\nc\nd\n"),
+            "  \"a\\n\"\n- \"b\\n\"\n+ \"c\\n\"\n+ \"d\\n\"\n"
+        );
+    }
+
+    #[test]
+    fn output_diff_marks_missing_lines_and_line_endings() {
+        assert_eq!(
+            output_diff("42\nbye\n", "42"),
+            "- \"42\\n\"\n+ \"42\"\n- \"bye\\n\"\n"
+        );
+    }
+}